@@ -0,0 +1,562 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+
+use crate::piping_server::ClientIdentity;
+
+/// Request to be authorized before senders and receivers are paired
+#[derive(Debug)]
+pub struct AuthorizationRequest<'a> {
+    pub method: &'a http::Method,
+    pub path: &'a str,
+    pub headers: &'a http::HeaderMap,
+    pub query_params: &'a HashMap<String, String>,
+    /// Address of the client if RemoteAddr request extension is given
+    pub remote_addr: Option<SocketAddr>,
+    /// Identity in the client certificate if ClientIdentity request extension is given
+    pub client_identity: Option<&'a ClientIdentity>,
+}
+
+impl AuthorizationRequest<'_> {
+    /// Role of the client, which is None for a method not to a pipe such as DELETE
+    pub fn role(&self) -> Option<Role> {
+        match *self.method {
+            http::Method::GET => Some(Role::Receive),
+            // HEAD with "upload" query parameter discovers the offset to resume an upload
+            http::Method::HEAD if self.query_params.contains_key("upload") => Some(Role::Send),
+            http::Method::HEAD => Some(Role::Receive),
+            http::Method::POST | http::Method::PUT | http::Method::PATCH => Some(Role::Send),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    Send,
+    Receive,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Authorization {
+    Allow,
+    Deny {
+        status: http::StatusCode,
+        message: String,
+    },
+    /// Allow the request as a request to the path
+    RewritePath(String),
+}
+
+impl Authorization {
+    pub fn unauthorized() -> Self {
+        Authorization::Deny {
+            status: http::StatusCode::UNAUTHORIZED,
+            message: "Unauthorized.".to_owned(),
+        }
+    }
+}
+
+/// Authorizer consulted for every request except preflight requests and the built-in pages such as /help
+/// (see PipingServer::with_authorizer())
+pub trait Authorizer: Send + Sync {
+    fn authorize(&self, req: &AuthorizationRequest) -> Authorization;
+
+    /// Value of WWW-Authenticate header in 401 responses
+    fn www_authenticate(&self) -> Option<http::HeaderValue> {
+        None
+    }
+}
+
+/// Allow requests with one of the tokens in `Authorization: Bearer <token>` header
+pub struct BearerTokenAuthorizer {
+    tokens: HashSet<String>,
+}
+
+impl BearerTokenAuthorizer {
+    pub fn new(tokens: impl IntoIterator<Item = String>) -> Self {
+        BearerTokenAuthorizer {
+            tokens: tokens.into_iter().collect(),
+        }
+    }
+}
+
+impl Authorizer for BearerTokenAuthorizer {
+    fn authorize(&self, req: &AuthorizationRequest) -> Authorization {
+        match get_authorization_credentials(req.headers, "Bearer") {
+            // NOTE: Compare with all the tokens in constant time not to leak the tokens by timing
+            Some(token)
+                if self.tokens.iter().fold(false, |found, expected| {
+                    constant_time_eq(token.as_bytes(), expected.as_bytes()) | found
+                }) =>
+            {
+                Authorization::Allow
+            }
+            _ => Authorization::unauthorized(),
+        }
+    }
+
+    fn www_authenticate(&self) -> Option<http::HeaderValue> {
+        Some(http::HeaderValue::from_static("Bearer"))
+    }
+}
+
+/// Allow requests with tokens scoped to roles and a path prefix, specified by `Authorization: Bearer <token>` header or "token" query parameter
+pub struct ScopedTokenAuthorizer {
+    token_to_scope: HashMap<String, TokenScope>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenScope {
+    pub roles: HashSet<Role>,
+    /// The token is valid on the paths under this prefix (e.g. "/ci/")
+    pub path_prefix: String,
+}
+
+impl TokenScope {
+    fn contains(&self, path: &str) -> bool {
+        match path.strip_prefix(&self.path_prefix) {
+            Some(rest) => {
+                self.path_prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/')
+            }
+            None => false,
+        }
+    }
+}
+
+impl ScopedTokenAuthorizer {
+    pub fn new(token_to_scope: HashMap<String, TokenScope>) -> Self {
+        ScopedTokenAuthorizer { token_to_scope }
+    }
+
+    /// Load tokens from a JSON file such as
+    /// {"tokens": [{"token": "mytoken", "roles": ["send"], "path_prefix": "/ci/"}]}
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("failed to read {}: {err}", path.display()))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let json: serde_json::Value = serde_json::from_str(content)?;
+        let Some(tokens) = json["tokens"].as_array() else {
+            anyhow::bail!("\"tokens\" should be an array");
+        };
+        let mut token_to_scope = HashMap::new();
+        for (i, token) in tokens.iter().enumerate() {
+            let Some(token_str) = token["token"].as_str() else {
+                anyhow::bail!("\"token\" should be a string in tokens[{i}]");
+            };
+            let Some(role_values) = token["roles"].as_array() else {
+                anyhow::bail!("\"roles\" should be an array in tokens[{i}]");
+            };
+            let roles = role_values
+                .iter()
+                .map(|role| match role.as_str() {
+                    Some("send") => Ok(Role::Send),
+                    Some("receive") => Ok(Role::Receive),
+                    _ => Err(anyhow::anyhow!(
+                        "role should be \"send\" or \"receive\" in tokens[{i}]"
+                    )),
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let path_prefix = match &token["path_prefix"] {
+                serde_json::Value::Null => "/",
+                serde_json::Value::String(path_prefix) if path_prefix.starts_with('/') => {
+                    path_prefix
+                }
+                _ => anyhow::bail!("\"path_prefix\" should start with '/' in tokens[{i}]"),
+            };
+            token_to_scope.insert(
+                token_str.to_owned(),
+                TokenScope {
+                    roles,
+                    path_prefix: path_prefix.to_owned(),
+                },
+            );
+        }
+        Ok(ScopedTokenAuthorizer { token_to_scope })
+    }
+}
+
+impl Authorizer for ScopedTokenAuthorizer {
+    fn authorize(&self, req: &AuthorizationRequest) -> Authorization {
+        let token = get_authorization_credentials(req.headers, "Bearer")
+            .or_else(|| req.query_params.get("token").map(|token| token.as_str()));
+        // NOTE: Compare with all the tokens in constant time not to leak the tokens by timing
+        let Some(scope) = token.and_then(|token| {
+            self.token_to_scope
+                .iter()
+                .fold(None, |found, (expected, scope)| {
+                    if constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+                        Some(scope)
+                    } else {
+                        found
+                    }
+                })
+        }) else {
+            return Authorization::unauthorized();
+        };
+        let forbidden = |message: String| Authorization::Deny {
+            status: http::StatusCode::FORBIDDEN,
+            message,
+        };
+        // Watching the status is allowed with either role
+        if let Some(watched_path) = req
+            .path
+            .strip_prefix(crate::piping_server::STATUS_PATH_PREFIX)
+            .filter(|watched_path| watched_path.starts_with('/'))
+        {
+            if !scope.contains(watched_path) {
+                return forbidden(format!(
+                    "The token is not allowed to watch '{watched_path}'."
+                ));
+            }
+            return Authorization::Allow;
+        }
+        let Some(role) = req.role() else {
+            return forbidden(format!(
+                "The token is not allowed to {} '{}'.",
+                req.method, req.path
+            ));
+        };
+        let action = match role {
+            Role::Send => "send to",
+            Role::Receive => "receive from",
+        };
+        if !scope.roles.contains(&role) || !scope.contains(req.path) {
+            return forbidden(format!(
+                "The token is not allowed to {action} '{}'.",
+                req.path
+            ));
+        }
+        Authorization::Allow
+    }
+
+    fn www_authenticate(&self) -> Option<http::HeaderValue> {
+        Some(http::HeaderValue::from_static("Bearer"))
+    }
+}
+
+/// Require `?expires=<unix time>&sig=<signature>` signed with the key of the role (see sign_path())
+/// NOTE: Requests of a role without a key are allowed
+pub struct UrlSigningAuthorizer {
+    send_key: Option<ring::hmac::Key>,
+    receive_key: Option<ring::hmac::Key>,
+}
+
+impl UrlSigningAuthorizer {
+    pub fn new(send_key: Option<&[u8]>, receive_key: Option<&[u8]>) -> Self {
+        let hmac_key = |key| ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key);
+        UrlSigningAuthorizer {
+            send_key: send_key.map(hmac_key),
+            receive_key: receive_key.map(hmac_key),
+        }
+    }
+}
+
+impl Authorizer for UrlSigningAuthorizer {
+    fn authorize(&self, req: &AuthorizationRequest) -> Authorization {
+        use base64::Engine as _;
+        let forbidden = |message: &str| Authorization::Deny {
+            status: http::StatusCode::FORBIDDEN,
+            message: message.to_owned(),
+        };
+        let key = match req.role() {
+            Some(Role::Send) => &self.send_key,
+            Some(Role::Receive) => &self.receive_key,
+            // NOTE: A URL cannot be signed without the role
+            None if self.send_key.is_some() || self.receive_key.is_some() => {
+                return forbidden("A signed URL is required.");
+            }
+            None => &None,
+        };
+        let Some(key) = key else {
+            return Authorization::Allow;
+        };
+        let (Some(expires), Some(sig)) =
+            (req.query_params.get("expires"), req.query_params.get("sig"))
+        else {
+            return forbidden("A signed URL is required.");
+        };
+        let Ok(expires) = expires.parse::<u64>() else {
+            return forbidden("Invalid \"expires\" query parameter.");
+        };
+        let Ok(sig) = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(sig) else {
+            return forbidden("Invalid signature.");
+        };
+        // NOTE: The role is always Some here
+        let message = signing_message(req.role().unwrap(), req.path, expires);
+        if ring::hmac::verify(key, message.as_bytes(), &sig).is_err() {
+            return forbidden("Invalid signature.");
+        }
+        if unix_time_now() > expires {
+            return forbidden("The signed URL has expired.");
+        }
+        Authorization::Allow
+    }
+}
+
+/// Path with the query parameters to allow the role on the path until the unix time (e.g. "/mypath?expires=1700000000&sig=...")
+pub fn sign_path(key: &[u8], role: Role, path: &str, expires: u64) -> String {
+    use base64::Engine as _;
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key);
+    let tag = ring::hmac::sign(&key, signing_message(role, path, expires).as_bytes());
+    let sig = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(tag.as_ref());
+    format!("{path}?expires={expires}&sig={sig}")
+}
+
+// NOTE: The role is signed so that a URL of a role cannot be used for the other role with the same key
+fn signing_message(role: Role, path: &str, expires: u64) -> String {
+    let role = match role {
+        Role::Send => "send",
+        Role::Receive => "receive",
+    };
+    format!("{role}\n{path}\n{expires}")
+}
+
+fn unix_time_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Allow requests with HTTP Basic authentication against users in an htpasswd file
+/// NOTE: bcrypt ("$2y$", `htpasswd -B`), MD5 ("$apr1$", `htpasswd -m`), SHA-1 ("{SHA}", `htpasswd -s`) and plain text (`htpasswd -p`) passwords are supported
+/// but crypt() (`htpasswd -d`) is not
+pub struct HtpasswdAuthorizer {
+    user_to_password: HashMap<String, HtpasswdPassword>,
+    // SHA-256 digests of the passwords last verified with bcrypt, which is too slow to run on every request
+    user_to_verified_digest: std::sync::Mutex<HashMap<String, ring::digest::Digest>>,
+}
+
+enum HtpasswdPassword {
+    Bcrypt(String),
+    Apr1 { salt: String, hash: String },
+    Sha1(Vec<u8>),
+    Plain(String),
+}
+
+impl HtpasswdAuthorizer {
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("failed to read {}: {err}", path.display()))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        use base64::Engine as _;
+        let mut user_to_password = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((user, password)) = line.split_once(':') else {
+                anyhow::bail!("invalid htpasswd line {}", i + 1);
+            };
+            let password = if let Some(hash) = password.strip_prefix("{SHA}") {
+                HtpasswdPassword::Sha1(base64::engine::general_purpose::STANDARD.decode(hash)?)
+            } else if ["$2y$", "$2b$", "$2a$"]
+                .iter()
+                .any(|prefix| password.starts_with(prefix))
+            {
+                HtpasswdPassword::Bcrypt(password.to_owned())
+            } else if let Some((salt, hash)) = password
+                .strip_prefix(APR1_MAGIC)
+                .and_then(|salt_and_hash| salt_and_hash.split_once('$'))
+            {
+                HtpasswdPassword::Apr1 {
+                    salt: salt.to_owned(),
+                    hash: hash.to_owned(),
+                }
+            } else if password.starts_with('$') {
+                anyhow::bail!(
+                    "unsupported password hash of '{user}' in htpasswd line {} (use `htpasswd -B`)",
+                    i + 1
+                );
+            } else if is_crypt_hash(password) {
+                // NOTE: Apache treats it as a crypt() hash on Unix, so it is not accepted as plain text
+                anyhow::bail!(
+                    "unsupported crypt() password hash of '{user}' in htpasswd line {} (use `htpasswd -B`)",
+                    i + 1
+                );
+            } else {
+                HtpasswdPassword::Plain(password.to_owned())
+            };
+            user_to_password.insert(user.to_owned(), password);
+        }
+        Ok(HtpasswdAuthorizer {
+            user_to_password,
+            user_to_verified_digest: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn verify(&self, user: &str, password: &str) -> bool {
+        match self.user_to_password.get(user) {
+            Some(HtpasswdPassword::Bcrypt(hash)) => {
+                let digest = ring::digest::digest(&ring::digest::SHA256, password.as_bytes());
+                let is_cached = self
+                    .user_to_verified_digest
+                    .lock()
+                    .unwrap()
+                    .get(user)
+                    .map_or(false, |verified_digest| {
+                        constant_time_eq(verified_digest.as_ref(), digest.as_ref())
+                    });
+                if is_cached {
+                    return true;
+                }
+                let verified = bcrypt::verify(password, hash).unwrap_or(false);
+                if verified {
+                    self.user_to_verified_digest
+                        .lock()
+                        .unwrap()
+                        .insert(user.to_owned(), digest);
+                }
+                verified
+            }
+            Some(HtpasswdPassword::Apr1 { salt, hash }) => constant_time_eq(
+                apr1_md5_hash(password.as_bytes(), salt.as_bytes()).as_bytes(),
+                hash.as_bytes(),
+            ),
+            Some(HtpasswdPassword::Sha1(hash)) => {
+                let digest = ring::digest::digest(
+                    &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+                    password.as_bytes(),
+                );
+                constant_time_eq(digest.as_ref(), hash)
+            }
+            Some(HtpasswdPassword::Plain(expected)) => {
+                constant_time_eq(password.as_bytes(), expected.as_bytes())
+            }
+            None => false,
+        }
+    }
+}
+
+const APR1_MAGIC: &str = "$apr1$";
+
+// Whether the password looks like a DES-based crypt() hash, which has 13 characters of "./0-9A-Za-z"
+fn is_crypt_hash(password: &str) -> bool {
+    password.len() == 13
+        && password
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'/')
+}
+
+// Hash part of Apache's MD5-based password hash "$apr1$<salt>$<hash>"
+// (ref: https://httpd.apache.org/docs/2.4/misc/password_encryptions.html)
+fn apr1_md5_hash(password: &[u8], salt: &[u8]) -> String {
+    use md5::{Digest as _, Md5};
+    const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+    let salt = &salt[..salt.len().min(8)];
+    let alternate: [u8; 16] = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize()
+        .into();
+    let mut hasher = Md5::new()
+        .chain_update(password)
+        .chain_update(APR1_MAGIC)
+        .chain_update(salt);
+    for chunk in password.chunks(16) {
+        hasher.update(&alternate[..chunk.len()]);
+    }
+    let mut n = password.len();
+    while n != 0 {
+        if n & 1 == 1 {
+            hasher.update([0u8]);
+        } else {
+            hasher.update(&password[..1]);
+        }
+        n >>= 1;
+    }
+    let mut digest: [u8; 16] = hasher.finalize().into();
+    for i in 0..1000 {
+        let mut hasher = Md5::new();
+        if i % 2 == 1 {
+            hasher.update(password);
+        } else {
+            hasher.update(digest);
+        }
+        if i % 3 != 0 {
+            hasher.update(salt);
+        }
+        if i % 7 != 0 {
+            hasher.update(password);
+        }
+        if i % 2 == 1 {
+            hasher.update(digest);
+        } else {
+            hasher.update(password);
+        }
+        digest = hasher.finalize().into();
+    }
+    let mut hash = String::with_capacity(22);
+    let mut push_base64 = |mut value: u32, n_chars: usize| {
+        for _ in 0..n_chars {
+            hash.push(ITOA64[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push_base64(
+            (digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32,
+            4,
+        );
+    }
+    push_base64(digest[11] as u32, 2);
+    hash
+}
+
+// Compare without returning early not to leak the position of the difference by timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Authorizer for HtpasswdAuthorizer {
+    fn authorize(&self, req: &AuthorizationRequest) -> Authorization {
+        use base64::Engine as _;
+        let Some(credentials) = get_authorization_credentials(req.headers, "Basic")
+            .and_then(|encoded| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .ok()
+            })
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+        else {
+            return Authorization::unauthorized();
+        };
+        match credentials.split_once(':') {
+            Some((user, password)) if self.verify(user, password) => Authorization::Allow,
+            _ => Authorization::unauthorized(),
+        }
+    }
+
+    fn www_authenticate(&self) -> Option<http::HeaderValue> {
+        Some(http::HeaderValue::from_static(
+            "Basic realm=\"Piping Server\", charset=\"UTF-8\"",
+        ))
+    }
+}
+
+// Credentials in Authorization header with the scheme (e.g. "Bearer mytoken")
+pub(crate) fn get_authorization_credentials<'a>(
+    headers: &'a http::HeaderMap,
+    scheme: &str,
+) -> Option<&'a str> {
+    let (actual_scheme, credentials) = headers
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .split_once(' ')?;
+    if !actual_scheme.eq_ignore_ascii_case(scheme) {
+        return None;
+    }
+    Some(credentials.trim())
+}
@@ -134,7 +134,7 @@ pub static INDEX: once_cell::sync::Lazy<String> = once_cell::sync::Lazy::new(||
 pub fn help(base_url: &Url) -> String {
     let version: &'static str = env!("CARGO_PKG_VERSION");
     let url = base_url.join("mypath").unwrap();
-    return std::format!(
+    std::format!(
         r#"Help for Piping Server (Rust) {version}
 (Repository: https://github.com/nwtgck/piping-server-rust)
 
@@ -148,6 +148,9 @@ curl -T myfile {url}
 # Send a text
 echo 'hello!' | curl -T - {url}
 
+# Send a file with progress every 5 seconds
+curl -T myfile '{url}?progress=5s'
+
 # Send a directory (zip)
 zip -q -r - ./mydir | curl -T - {url}
 
@@ -160,7 +163,7 @@ cat myfile | openssl aes-256-cbc | curl -T - {url}
 ## Get
 curl {url} | openssl aes-256-cbc -d
 "#
-    );
+    )
 }
 
 pub fn no_script_html(query_params: &HashMap<String, String>, style_nonce: &str) -> String {
@@ -219,7 +222,7 @@ pub fn no_script_html(query_params: &HashMap<String, String>, style_nonce: &str)
 
     let version = env!("CARGO_PKG_VERSION");
 
-    return std::format!(
+    std::format!(
         // language=html
         r#"<!DOCTYPE html>
 <html lang="en">
@@ -262,5 +265,5 @@ pub fn no_script_html(query_params: &HashMap<String, String>, style_nonce: &str)
 </body>
 </html>
 "#
-    );
+    )
 }
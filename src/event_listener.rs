@@ -0,0 +1,61 @@
+use std::net::SocketAddr;
+
+/// Listener of events in the lifecycle of transfers (see PipingServer::with_event_listener())
+/// NOTE: Callbacks are called in the tasks handling requests and transfers, so they should return quickly
+pub trait PipingEventListener: Send + Sync {
+    /// A sender started waiting for receivers
+    fn on_sender_waiting(&self, _event: &WaitingEvent) {}
+    /// A receiver started waiting for a sender
+    fn on_receiver_waiting(&self, _event: &WaitingEvent) {}
+    /// A sender and all receivers were connected and the transfer started
+    fn on_transfer_start(&self, _event: &TransferStartEvent) {}
+    /// Bytes transferred so far, notified periodically during a transfer
+    fn on_progress(&self, _event: &ProgressEvent) {}
+    /// All receivers received the data
+    fn on_transfer_completed(&self, _event: &TransferEndEvent) {}
+    /// The transfer ended before all receivers received the data
+    fn on_transfer_aborted(&self, _event: &TransferEndEvent) {}
+    /// A request was rejected
+    fn on_rejected(&self, _event: &RejectionEvent) {}
+}
+
+#[derive(Clone, Debug)]
+pub struct WaitingEvent {
+    pub path: String,
+    pub n_receivers: u32,
+    /// Address of the waiting party if RemoteAddr request extension is given
+    pub remote_addr: Option<SocketAddr>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransferStartEvent {
+    pub path: String,
+    /// Request headers of the sender except credentials such as Authorization header
+    pub headers: http::HeaderMap,
+    pub sender_addr: Option<SocketAddr>,
+    pub receiver_addrs: Vec<Option<SocketAddr>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProgressEvent {
+    pub path: String,
+    pub n_bytes: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransferEndEvent {
+    pub path: String,
+    pub n_bytes: u64,
+    pub duration: std::time::Duration,
+    /// Reason of the abort such as "stalled", "sender_failed", "partially_delivered" and "all_receivers_disconnected" (None if completed)
+    pub abort_reason: Option<&'static str>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RejectionEvent {
+    pub method: http::Method,
+    pub path: String,
+    pub remote_addr: Option<SocketAddr>,
+    pub status: http::StatusCode,
+    pub reason: String,
+}
@@ -0,0 +1,95 @@
+use http_body_util::BodyExt as _;
+
+use crate::piping_server::{PipingServer, ResponseBody};
+use crate::util::{empty_body, full_body};
+
+/// Wrapper of PipingServer::handle() which redirects HTTP requests to HTTPS and adds Strict-Transport-Security header to HTTPS responses
+#[derive(Clone)]
+pub struct HttpsUpgrade {
+    piping_server: PipingServer,
+    redirect_https_port: Option<u16>,
+    hsts: Option<http::HeaderValue>,
+}
+
+impl HttpsUpgrade {
+    /// Wrap the Piping Server without redirects nor Strict-Transport-Security header
+    pub fn new(piping_server: PipingServer) -> Self {
+        HttpsUpgrade {
+            piping_server,
+            redirect_https_port: None,
+            hsts: None,
+        }
+    }
+
+    /// Respond to HTTP requests with 308 Permanent Redirect to the HTTPS port, which keeps the method and the body
+    pub fn with_redirect_to_https_port(mut self, https_port: u16) -> Self {
+        self.redirect_https_port = Some(https_port);
+        self
+    }
+
+    /// Add Strict-Transport-Security header with the max-age to HTTPS responses
+    pub fn with_hsts_max_age(mut self, max_age: std::time::Duration) -> Self {
+        self.hsts = Some(
+            http::HeaderValue::try_from(format!("max-age={}", max_age.as_secs()))
+                .expect("max-age should be a valid header value"),
+        );
+        self
+    }
+
+    pub async fn handle(
+        self,
+        uses_https: bool,
+        req: http::Request<hyper::body::Incoming>,
+    ) -> anyhow::Result<http::Response<ResponseBody>> {
+        if uses_https {
+            let mut res = self.piping_server.handle(true, req).await?;
+            if let Some(hsts) = self.hsts {
+                res.headers_mut()
+                    .insert(http::header::STRICT_TRANSPORT_SECURITY, hsts);
+            }
+            return Ok(res);
+        }
+        let Some(https_port) = self.redirect_https_port else {
+            return self.piping_server.handle(false, req).await;
+        };
+        Ok(https_redirect_response(https_port, &req))
+    }
+}
+
+fn https_redirect_response<B>(
+    https_port: u16,
+    req: &http::Request<B>,
+) -> http::Response<ResponseBody> {
+    // NOTE: The authority is in the URI in HTTP/2 and in Host header in HTTP/1.1
+    let authority: Option<http::uri::Authority> = match req.uri().authority() {
+        Some(authority) => Some(authority.clone()),
+        None => req
+            .headers()
+            .get(http::header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(|host| host.parse().ok()),
+    };
+    let Some(authority) = authority else {
+        return http::Response::builder()
+            .status(http::StatusCode::BAD_REQUEST)
+            .header("Content-Type", "text/plain")
+            .body(
+                full_body("[ERROR] Host header is required to redirect to HTTPS.\n").boxed_unsync(),
+            )
+            .unwrap();
+    };
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    let location = if https_port == 443 {
+        format!("https://{}{path_and_query}", authority.host())
+    } else {
+        format!("https://{}:{https_port}{path_and_query}", authority.host())
+    };
+    http::Response::builder()
+        .status(http::StatusCode::PERMANENT_REDIRECT)
+        .header(http::header::LOCATION, location)
+        .body(empty_body().boxed_unsync())
+        .unwrap()
+}
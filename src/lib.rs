@@ -1,4 +1,12 @@
+pub mod auth;
 mod dynamic_resources;
+pub mod event_listener;
+pub mod https_upgrade;
 mod macros;
 pub mod piping_server;
+mod sender_event;
+#[cfg(feature = "tower")]
+mod service;
+mod spool;
+mod status;
 pub mod util;
@@ -2,7 +2,11 @@ use clap::Parser as _;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use piping_server::piping_server::PipingServer;
+use piping_server::auth::{
+    BearerTokenAuthorizer, HtpasswdAuthorizer, Role, ScopedTokenAuthorizer, UrlSigningAuthorizer,
+};
+use piping_server::https_upgrade::HttpsUpgrade;
+use piping_server::piping_server::{PipingServer, PipingServerConfig, RemoteAddr};
 use piping_server::util;
 
 /// Piping Server in Rust
@@ -23,16 +27,171 @@ struct Args {
     /// HTTPS port
     #[clap(long)]
     https_port: Option<u16>,
+    /// Port to serve both HTTP and HTTPS by detecting TLS instead of --http-port and --https-port
+    #[clap(long, value_name = "PORT", conflicts_with_all = ["enable_https", "https_port"])]
+    unified_port: Option<u16>,
+    /// Redirect HTTP requests to the HTTPS port with 308 Permanent Redirect
+    #[clap(long)]
+    redirect_http_to_https: bool,
+    /// Max-age of Strict-Transport-Security header added to HTTPS responses
+    #[clap(long, value_name = "SECONDS")]
+    hsts_max_age: Option<u64>,
     /// Certification path
     #[clap(long)]
     crt_path: Option<String>,
     /// Private key path
     #[clap(long)]
     key_path: Option<String>,
+    /// Maximum seconds for a TLS handshake
+    #[clap(long, value_name = "SECONDS", default_value = "10")]
+    tls_handshake_timeout: u64,
+    /// Certificate for a host name selected by SNI (can be specified multiple times, falling back to --crt-path and --key-path)
+    #[clap(long = "tls-cert", value_name = "HOST=CRT,KEY")]
+    tls_certs: Vec<util::HostCert>,
+    /// Directory of <host>.crt and <host>.key pairs selected by SNI
+    #[clap(long, value_name = "DIR")]
+    tls_cert_dir: Option<std::path::PathBuf>,
+    /// CA certificates path to require client certificates on HTTPS
+    #[clap(long, value_name = "FILE")]
+    client_ca_path: Option<std::path::PathBuf>,
+    /// Accept also clients without certificates when --client-ca-path is specified
+    #[clap(long, requires = "client_ca_path")]
+    client_cert_optional: bool,
+    /// Maximum seconds for which a sender waits for receivers
+    #[clap(long, value_name = "SECONDS")]
+    sender_wait_timeout: Option<u64>,
+    /// Maximum seconds for which a receiver waits for a sender
+    #[clap(long, value_name = "SECONDS")]
+    receiver_wait_timeout: Option<u64>,
+    /// Seconds without any data moving after which a transfer is aborted
+    #[clap(long, value_name = "SECONDS")]
+    idle_transfer_timeout: Option<u64>,
+    /// Seconds for which an interrupted resumable upload waits for the sender to resume
+    #[clap(long, value_name = "SECONDS", default_value = "60")]
+    upload_resumption_grace_period: u64,
+    /// Bytes buffered per transfer so that a reconnected receiver can resume with Range header
+    #[clap(long, value_name = "BYTES")]
+    resume_buffer_size: Option<usize>,
+    /// Seconds for which a transfer whose receivers are all disconnected waits for one to reconnect
+    #[clap(long, value_name = "SECONDS", default_value = "60")]
+    receiver_resumption_grace_period: u64,
+    /// Directory to store data sent with ?store=<duration> until a receiver connects
+    #[clap(long, value_name = "DIR")]
+    spool_dir: Option<std::path::PathBuf>,
+    /// Maximum bytes of data stored by a sender
+    #[clap(long, value_name = "BYTES")]
+    spool_max_file_size: Option<u64>,
+    /// Maximum total bytes of data in the spool directory
+    #[clap(long, value_name = "BYTES")]
+    spool_max_total_size: Option<u64>,
+    /// Maximum seconds for which data is stored
+    #[clap(long, value_name = "SECONDS")]
+    spool_max_ttl: Option<u64>,
+    /// Token required in Authorization: Bearer header (can be specified multiple times)
+    #[clap(long = "auth-token", value_name = "TOKEN")]
+    auth_tokens: Vec<String>,
+    /// Path of htpasswd file with bcrypt, MD5, SHA-1 or plain text passwords (crypt() is not supported) to require HTTP Basic authentication
+    #[clap(long, value_name = "FILE", conflicts_with = "auth_tokens")]
+    htpasswd_path: Option<std::path::PathBuf>,
+    /// Path of JSON file of tokens scoped to roles and a path prefix (e.g. {"tokens": [{"token": "mytoken", "roles": ["send"], "path_prefix": "/ci/"}]})
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["auth_tokens", "htpasswd_path"])]
+    scoped_tokens_path: Option<std::path::PathBuf>,
+    /// Key to require URLs signed by `piping-server sign` for both senders and receivers
+    #[clap(long, value_name = "KEY", conflicts_with_all = ["auth_tokens", "htpasswd_path", "scoped_tokens_path"])]
+    url_signing_key: Option<String>,
+    /// Key to require signed URLs for senders instead of --url-signing-key
+    #[clap(long, value_name = "KEY", conflicts_with_all = ["auth_tokens", "htpasswd_path", "scoped_tokens_path"])]
+    send_url_signing_key: Option<String>,
+    /// Key to require signed URLs for receivers instead of --url-signing-key
+    #[clap(long, value_name = "KEY", conflicts_with_all = ["auth_tokens", "htpasswd_path", "scoped_tokens_path"])]
+    receive_url_signing_key: Option<String>,
 
     /// Print version
     #[clap(long, action = clap::ArgAction::Version, value_parser = clap::value_parser!(bool))]
     version: (),
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+enum Command {
+    /// Print the path with the query parameters of a signed URL
+    Sign {
+        /// Path to sign (e.g. /mypath)
+        path: String,
+        /// Role allowed by the signed URL
+        #[clap(long, default_value = "receive", value_parser = ["send", "receive"])]
+        role: String,
+        /// Seconds for which the signed URL is valid
+        #[clap(long, value_name = "SECONDS", default_value = "3600")]
+        expires_in: u64,
+        /// Key to sign the URL
+        #[clap(long, value_name = "KEY")]
+        url_signing_key: String,
+    },
+}
+
+impl Args {
+    // Configuration of Piping Server specified by the arguments
+    fn piping_server_config(&self) -> PipingServerConfig {
+        PipingServerConfig {
+            sender_wait_timeout: self.sender_wait_timeout.map(std::time::Duration::from_secs),
+            receiver_wait_timeout: self
+                .receiver_wait_timeout
+                .map(std::time::Duration::from_secs),
+            idle_transfer_timeout: self
+                .idle_transfer_timeout
+                .map(std::time::Duration::from_secs),
+            upload_resumption_grace_period: std::time::Duration::from_secs(
+                self.upload_resumption_grace_period,
+            ),
+            resume_buffer_size: self.resume_buffer_size,
+            receiver_resumption_grace_period: std::time::Duration::from_secs(
+                self.receiver_resumption_grace_period,
+            ),
+            spool_dir: self.spool_dir.clone(),
+            spool_max_file_size: self.spool_max_file_size,
+            spool_max_total_size: self.spool_max_total_size,
+            spool_max_ttl: self.spool_max_ttl.map(std::time::Duration::from_secs),
+            ..Default::default()
+        }
+    }
+
+    // TLS config reloaded when certificates are changed
+    fn hot_reload_tls_cfg(
+        &self,
+    ) -> anyhow::Result<Arc<tokio::sync::RwLock<Arc<rustls::ServerConfig>>>> {
+        let client_auth = self.client_ca_path.clone().map(|ca_path| util::ClientAuth {
+            ca_path,
+            required: !self.client_cert_optional,
+        });
+        let tokio_handle = tokio::runtime::Handle::current();
+        if self.tls_certs.is_empty() && self.tls_cert_dir.is_none() {
+            let (Some(crt_path), Some(key_path)) = (self.crt_path.clone(), self.key_path.clone())
+            else {
+                anyhow::bail!("--crt-path and --key-path should be specified");
+            };
+            return Ok(util::hot_reload_tls_cfg_with_client_auth(
+                tokio_handle,
+                crt_path,
+                key_path,
+                client_auth,
+            ));
+        }
+        let default_cert = match (&self.crt_path, &self.key_path) {
+            (Some(crt_path), Some(key_path)) => Some((crt_path.into(), key_path.into())),
+            (None, None) => None,
+            _ => anyhow::bail!("--crt-path and --key-path should be specified together"),
+        };
+        // NOTE: --tls-cert overrides the certificate for the same host in --tls-cert-dir
+        let mut host_certs = match &self.tls_cert_dir {
+            Some(tls_cert_dir) => util::host_certs_in_dir(tls_cert_dir)?,
+            None => vec![],
+        };
+        host_certs.extend(self.tls_certs.iter().cloned());
+        util::hot_reload_sni_tls_cfg(tokio_handle, default_cert, host_certs, client_auth)
+    }
 }
 
 #[tokio::main]
@@ -40,92 +199,243 @@ async fn main() -> anyhow::Result<()> {
     // Parse arguments
     let args = Args::parse();
 
+    if let Some(Command::Sign {
+        path,
+        role,
+        expires_in,
+        url_signing_key,
+    }) = &args.command
+    {
+        let role = if role == "send" {
+            Role::Send
+        } else {
+            Role::Receive
+        };
+        let expires = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs()
+            + expires_in;
+        println!(
+            "{}",
+            piping_server::auth::sign_path(url_signing_key.as_bytes(), role, path, expires)
+        );
+        return Ok(());
+    }
+
     // Set default log level
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let piping_server = PipingServer::new();
+    let mut piping_server = PipingServer::try_with_config(args.piping_server_config())?;
+    if !args.auth_tokens.is_empty() {
+        piping_server =
+            piping_server.with_authorizer(BearerTokenAuthorizer::new(args.auth_tokens.clone()));
+    }
+    if let Some(htpasswd_path) = &args.htpasswd_path {
+        piping_server =
+            piping_server.with_authorizer(HtpasswdAuthorizer::from_file(htpasswd_path)?);
+    }
+    if let Some(scoped_tokens_path) = &args.scoped_tokens_path {
+        piping_server =
+            piping_server.with_authorizer(ScopedTokenAuthorizer::from_file(scoped_tokens_path)?);
+    }
+    let send_url_signing_key = args
+        .send_url_signing_key
+        .as_ref()
+        .or(args.url_signing_key.as_ref());
+    let receive_url_signing_key = args
+        .receive_url_signing_key
+        .as_ref()
+        .or(args.url_signing_key.as_ref());
+    if send_url_signing_key.is_some() || receive_url_signing_key.is_some() {
+        piping_server = piping_server.with_authorizer(UrlSigningAuthorizer::new(
+            send_url_signing_key.map(|key| key.as_bytes()),
+            receive_url_signing_key.map(|key| key.as_bytes()),
+        ));
+    }
+
+    let mut https_upgrade = HttpsUpgrade::new(piping_server);
+    // Port where HTTPS is served
+    let https_port = if args.enable_https {
+        args.https_port
+    } else {
+        args.unified_port
+    };
+    if args.redirect_http_to_https {
+        let Some(https_port) = https_port else {
+            anyhow::bail!(
+                "--redirect-http-to-https requires --enable-https with --https-port or --unified-port"
+            );
+        };
+        https_upgrade = https_upgrade.with_redirect_to_https_port(https_port);
+    }
+    if let Some(hsts_max_age) = args.hsts_max_age {
+        if https_port.is_none() {
+            anyhow::bail!(
+                "--hsts-max-age requires --enable-https with --https-port or --unified-port"
+            );
+        }
+        https_upgrade =
+            https_upgrade.with_hsts_max_age(std::time::Duration::from_secs(hsts_max_age));
+    }
 
     let version = env!("CARGO_PKG_VERSION");
     log::info!("Piping Server (Rust) {version}");
 
     let serve_http = {
-        let piping_server = piping_server.clone();
+        let https_upgrade = https_upgrade.clone();
         let args = args.clone();
         async move {
+            if args.unified_port.is_some() {
+                return Ok(());
+            }
             let tcp_listener =
                 tokio::net::TcpListener::bind(SocketAddr::new(args.host, args.http_port)).await?;
             log::info!("HTTP server is listening on {}...", args.http_port);
-            let piping_server_service =
-                hyper::service::service_fn(move |req| piping_server.clone().handle(false, req));
 
             loop {
-                let (stream, _) = tcp_listener.accept().await?;
-                let piping_server_service = piping_server_service.clone();
-                tokio::task::spawn(async move {
-                    if let Err(err) = hyper_util::server::conn::auto::Builder::new(
-                        hyper_util::rt::tokio::TokioExecutor::new(),
-                    )
-                    .serve_connection(hyper_util::rt::TokioIo::new(stream), piping_server_service)
-                    .await
-                    {
-                        log::error!("Failed to serve HTTP connection: {err:?}");
-                    }
-                });
+                let (stream, remote_addr) = tcp_listener.accept().await?;
+                tokio::task::spawn(serve_http_connection(
+                    https_upgrade.clone(),
+                    stream,
+                    remote_addr,
+                ));
             }
             #[allow(unreachable_code)]
             Ok::<_, anyhow::Error>(())
         }
     };
 
-    let serve_https = (|| async {
+    let serve_https = async {
         if !args.enable_https {
             return Ok(());
         }
-        let (Some(https_port), Some(crt_path), Some(key_path)) =
-            (args.https_port, args.crt_path, args.key_path)
-        else {
-            anyhow::bail!("--https-port, --crt-path and --key-path should be specified");
+        let Some(https_port) = args.https_port else {
+            anyhow::bail!("--https-port should be specified");
         };
-        let piping_server = piping_server.clone();
-
-        let tokio_handle = tokio::runtime::Handle::current();
-        let tls_cfg_rwlock_arc: Arc<tokio::sync::RwLock<Arc<rustls::ServerConfig>>> =
-            util::hot_reload_tls_cfg(tokio_handle, crt_path, key_path);
+        let https_upgrade = https_upgrade.clone();
+        let tls_cfg_rwlock_arc = args.hot_reload_tls_cfg()?;
 
         let tcp_listener =
             tokio::net::TcpListener::bind(SocketAddr::new(args.host, https_port)).await?;
         log::info!("HTTPS server is listening on {https_port}...");
 
-        let piping_server_service =
-            hyper::service::service_fn(move |req| piping_server.clone().handle(true, req));
+        let tls_handshake_timeout = std::time::Duration::from_secs(args.tls_handshake_timeout);
+        loop {
+            let (stream, remote_addr) = tcp_listener.accept().await?;
+            let rustls_config = tls_cfg_rwlock_arc.clone().read().await.clone();
+            // NOTE: The handshake is done in the spawned task not to block accepting other connections
+            tokio::task::spawn(serve_https_connection(
+                https_upgrade.clone(),
+                rustls_config,
+                tls_handshake_timeout,
+                stream,
+                remote_addr,
+            ));
+        }
+    };
+
+    let serve_unified = async {
+        let Some(unified_port) = args.unified_port else {
+            return Ok(());
+        };
+        let https_upgrade = https_upgrade.clone();
+        let tls_cfg_rwlock_arc = args.hot_reload_tls_cfg()?;
+
+        let tcp_listener =
+            tokio::net::TcpListener::bind(SocketAddr::new(args.host, unified_port)).await?;
+        log::info!("HTTP and HTTPS server is listening on {unified_port}...");
 
+        let tls_handshake_timeout = std::time::Duration::from_secs(args.tls_handshake_timeout);
         loop {
-            let (stream, _) = tcp_listener.accept().await?;
+            let (stream, remote_addr) = tcp_listener.accept().await?;
             let rustls_config = tls_cfg_rwlock_arc.clone().read().await.clone();
-            let stream = match tokio_rustls::TlsAcceptor::from(rustls_config)
-                .accept(stream)
-                .await
-            {
-                Ok(stream) => stream,
-                Err(err) => {
-                    log::error!("Failed to accept TLS connection: {err:?}");
-                    continue;
-                }
-            };
-            let piping_server_service = piping_server_service.clone();
+            let https_upgrade = https_upgrade.clone();
             tokio::task::spawn(async move {
-                if let Err(err) = hyper_util::server::conn::auto::Builder::new(
-                    hyper_util::rt::tokio::TokioExecutor::new(),
-                )
-                .serve_connection(hyper_util::rt::TokioIo::new(stream), piping_server_service)
-                .await
+                // NOTE: A TLS connection starts with a handshake record (content type 22)
+                const TLS_HANDSHAKE_CONTENT_TYPE: u8 = 0x16;
+                let mut first_byte = [0u8; 1];
+                match tokio::time::timeout(tls_handshake_timeout, stream.peek(&mut first_byte))
+                    .await
                 {
-                    log::error!("Failed to serve HTTPS connection: {err:?}");
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(_)) if first_byte[0] == TLS_HANDSHAKE_CONTENT_TYPE => {
+                        serve_https_connection(
+                            https_upgrade,
+                            rustls_config,
+                            tls_handshake_timeout,
+                            stream,
+                            remote_addr,
+                        )
+                        .await
+                    }
+                    Ok(Ok(_)) => serve_http_connection(https_upgrade, stream, remote_addr).await,
+                    Ok(Err(err)) => {
+                        log::error!("Failed to read the first byte from {remote_addr}: {err:?}")
+                    }
+                    Err(_) => log::error!("Reading the first byte from {remote_addr} timed out"),
                 }
             });
         }
-    })();
+    };
 
-    let _: ((), ()) = futures::try_join!(serve_http, serve_https)?;
+    let _: ((), (), ()) = futures::try_join!(serve_http, serve_https, serve_unified)?;
     Ok(())
 }
+
+async fn serve_http_connection(
+    https_upgrade: HttpsUpgrade,
+    stream: tokio::net::TcpStream,
+    remote_addr: SocketAddr,
+) {
+    let piping_server_service = hyper::service::service_fn(move |mut req: hyper::Request<_>| {
+        req.extensions_mut().insert(RemoteAddr(remote_addr));
+        https_upgrade.clone().handle(false, req)
+    });
+    if let Err(err) =
+        hyper_util::server::conn::auto::Builder::new(hyper_util::rt::tokio::TokioExecutor::new())
+            .serve_connection(hyper_util::rt::TokioIo::new(stream), piping_server_service)
+            .await
+    {
+        log::error!("Failed to serve HTTP connection: {err:?}");
+    }
+}
+
+async fn serve_https_connection(
+    https_upgrade: HttpsUpgrade,
+    rustls_config: Arc<rustls::ServerConfig>,
+    tls_handshake_timeout: std::time::Duration,
+    stream: tokio::net::TcpStream,
+    remote_addr: SocketAddr,
+) {
+    let stream = match tokio::time::timeout(
+        tls_handshake_timeout,
+        tokio_rustls::TlsAcceptor::from(rustls_config).accept(stream),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => {
+            log::error!("Failed to accept TLS connection: {err:?}");
+            return;
+        }
+        Err(_) => {
+            log::error!("TLS handshake with {remote_addr} timed out");
+            return;
+        }
+    };
+    let client_identity = util::client_identity(stream.get_ref().1);
+    let piping_server_service = hyper::service::service_fn(move |mut req: hyper::Request<_>| {
+        req.extensions_mut().insert(RemoteAddr(remote_addr));
+        if let Some(client_identity) = client_identity.clone() {
+            req.extensions_mut().insert(client_identity);
+        }
+        https_upgrade.clone().handle(true, req)
+    });
+    if let Err(err) =
+        hyper_util::server::conn::auto::Builder::new(hyper_util::rt::tokio::TokioExecutor::new())
+            .serve_connection(hyper_util::rt::TokioIo::new(stream), piping_server_service)
+            .await
+    {
+        log::error!("Failed to serve HTTPS connection: {err:?}");
+    }
+}
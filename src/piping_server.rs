@@ -1,15 +1,26 @@
 use anyhow::anyhow;
-use futures::{SinkExt as _, StreamExt as _, TryStreamExt as _};
+use core::pin::Pin;
+use futures::{FutureExt as _, SinkExt as _, StreamExt as _, TryStreamExt as _};
 use http_body_util::BodyExt as _;
 use hyper::body::Bytes;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use url::Url;
 
+use crate::auth::{Authorization, AuthorizationRequest, Authorizer};
 use crate::dynamic_resources;
+use crate::event_listener::{
+    PipingEventListener, ProgressEvent, RejectionEvent, TransferEndEvent, TransferStartEvent,
+    WaitingEvent,
+};
+use crate::sender_event::{ReceiverDelivery, SenderEvent, SenderEventFormat, SenderEventSender};
+use crate::spool::{Spool, SpoolLimits, SpoolMetadata, SpoolStoreError};
+use crate::status::{StatusEvent, StatusHub};
 use crate::util::{
-    empty_body, finish_detectable_body, full_body, query_param_to_hash_map, FinishDetectableBody,
-    HeaderValuesBuilder, OptionHeaderBuilder,
+    abortable_body, counting_body, empty_body, finish_detectable_body, full_body, parse_duration,
+    query_param_to_hash_map, AbortableBody, BytesRingBuffer, CountingBody, FinishDetectableBody,
+    HeaderValuesBuilder, OptionHeaderBuilder, ReadAheadBody,
 };
 
 pub mod reserved_paths {
@@ -24,20 +35,64 @@ pub mod reserved_paths {
 }
 
 pub const NO_SCRIPT_PATH_QUERY_PARAMETER_NAME: &str = "path";
+// GET /_status/mypath streams the status of /mypath
+pub const STATUS_PATH_PREFIX: &str = "/_status";
+
+// Interval to check whether waiting senders and receivers are still connected
+const WAITING_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+// A waiting sender's request body is read ahead up to this size to detect its disconnection
+const SENDER_READ_AHEAD_LIMIT_BYTES: usize = 64 * 1024;
+// Interval to notify status watchers of the progress of a transfer
+const STATUS_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+// Maximum duration to deliver the last event of a transfer to the sender when --idle-transfer-timeout is not specified
+const LAST_SENDER_EVENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Request body of Piping Server
+pub type RequestBody = http_body_util::combinators::UnsyncBoxBody<Bytes, anyhow::Error>;
+
+type SenderRequestBody = ReadAheadBody<RequestBody>;
 
 struct DataSender {
+    n_receivers: u32,
+    wait_timeout: Option<std::time::Duration>,
+    connected_at: std::time::Instant,
+    progress_interval: Option<std::time::Duration>,
+    // Token of the upload resource to resume the upload (not resumable if None)
+    upload_token: Option<String>,
+    // Whether the request body completes the upload (Upload-Complete: ?1)
+    upload_complete: bool,
+    remote_addr: Option<std::net::SocketAddr>,
     // request
     req_headers: http::header::HeaderMap,
-    req_body: hyper::body::Incoming,
+    req_body: SenderRequestBody,
     // response
-    res_body_tx: futures::channel::mpsc::Sender<Result<http_body::Frame<Bytes>, anyhow::Error>>,
+    res_event_tx: SenderEventSender,
+}
+
+impl DataSender {
+    // Read the request body ahead to make the disconnection detectable
+    // because an HTTP/1 connection is not read while the request body is not consumed
+    fn read_ahead(&mut self) {
+        let _ = futures::future::poll_fn(|cx| {
+            Pin::new(&mut self.req_body).poll_read_ahead(cx, SENDER_READ_AHEAD_LIMIT_BYTES)
+        })
+        .now_or_never();
+    }
+
+    fn is_disconnected(&self) -> bool {
+        self.req_body.is_errored() || self.res_event_tx.is_closed()
+    }
 }
 
-type IncomingMapErrBody =
-    http_body_util::combinators::MapErr<hyper::body::Incoming, fn(hyper::Error) -> anyhow::Error>;
+fn is_wait_timed_out(
+    wait_timeout: Option<std::time::Duration>,
+    connected_at: std::time::Instant,
+) -> bool {
+    wait_timeout.map_or(false, |timeout| connected_at.elapsed() >= timeout)
+}
 
 type BodyStreamNewMapToBytesStream = futures::stream::Map<
-    http_body_util::BodyStream<IncomingMapErrBody>,
+    http_body_util::BodyStream<SenderRequestBody>,
     fn(anyhow::Result<hyper::body::Frame<Bytes>>) -> anyhow::Result<Bytes>,
 >;
 
@@ -50,63 +105,685 @@ type MultipartFieldMapToFrameStream = futures::stream::Map<
 
 #[auto_enums::enum_derive(http_body1::Body)]
 enum TransferRequestBody {
-    Incoming(
-        http_body_util::combinators::MapErr<
-            hyper::body::Incoming,
-            fn(hyper::Error) -> anyhow::Error,
-        >,
-    ),
+    Incoming(SenderRequestBody),
     Multipart(http_body_util::StreamBody<MultipartFieldMapToFrameStream>),
     #[allow(dead_code)]
     Box(http_body_util::combinators::BoxBody<Bytes, anyhow::Error>),
 }
 
-type DataReceiverResponseBody = FinishDetectableBody<TransferRequestBody>;
+type DataReceiverResponseBody = FinishDetectableBody<
+    CountingBody<
+        AbortableBody<
+            http_body_util::StreamBody<
+                futures::channel::mpsc::Receiver<Result<http_body::Frame<Bytes>, anyhow::Error>>,
+            >,
+        >,
+    >,
+>;
+
+// Sending side of a receiver's response body
+struct ReceiverBodySender {
+    frame_tx: futures::channel::mpsc::Sender<Result<http_body::Frame<Bytes>, anyhow::Error>>,
+    aborter: futures::channel::oneshot::Sender<anyhow::Error>,
+    // Notified when the response body is fully written
+    finish_waiter: futures::channel::oneshot::Receiver<()>,
+    // Position in the data up to which the response body has been written
+    n_delivered_bytes: Arc<AtomicU64>,
+}
+
+impl ReceiverBodySender {
+    // Make the receiver's response body end with the error
+    fn abort(self, err: anyhow::Error) {
+        let _ = self.aborter.send(err);
+    }
+
+    fn n_delivered_bytes(&self) -> u64 {
+        self.n_delivered_bytes.load(Ordering::Relaxed)
+    }
+}
+
+// Error response for a receiver whose response is returned from a transfer
+fn receiver_error_response(
+    status: http::StatusCode,
+    message: String,
+) -> http::Response<DataReceiverResponseBody> {
+    let (mut body_sender, body) = receiver_body_channel(1, None);
+    let _ = body_sender
+        .frame_tx
+        .try_send(Ok(http_body::Frame::data(Bytes::from(format!(
+            "[ERROR] {message}\n"
+        )))));
+    http::Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain")
+        .extension(Rejection { reason: message })
+        .body(body)
+        .unwrap()
+}
+
+fn receiver_body_channel(
+    buffer_size: usize,
+    content_length: Option<u64>,
+) -> (ReceiverBodySender, DataReceiverResponseBody) {
+    let (frame_tx, frame_rx) = futures::channel::mpsc::channel(buffer_size);
+    let (body, aborter) = abortable_body(http_body_util::StreamBody::new(frame_rx));
+    let n_delivered_bytes = Arc::new(AtomicU64::new(0));
+    let body = counting_body(body, Arc::clone(&n_delivered_bytes));
+    let (body, finish_waiter) = finish_detectable_body(body);
+    let body = body.with_content_length(content_length);
+    (
+        ReceiverBodySender {
+            frame_tx,
+            aborter,
+            finish_waiter,
+            n_delivered_bytes,
+        },
+        body,
+    )
+}
+
+// Request to resume an interrupted upload at the offset
+struct UploadResumption {
+    offset: u64,
+    // Whether the body completes the upload (Upload-Complete: ?1)
+    complete: bool,
+    body: SenderRequestBody,
+    res_event_tx: SenderEventSender,
+    // Replied with the current offset if the offset does not match
+    reply_tx: futures::channel::oneshot::Sender<Result<(), u64>>,
+}
+
+// Ongoing transfer whose upload can be resumed
+struct ResumableUpload {
+    path: String,
+    n_transferred_bytes: Arc<AtomicU64>,
+    resumption_tx: futures::channel::mpsc::Sender<UploadResumption>,
+}
+
+// Request of a reconnected receiver to receive the transfer from the offset
+struct ReceiverReattachment {
+    offset: u64,
+    res_sender: futures::channel::oneshot::Sender<http::Response<DataReceiverResponseBody>>,
+}
 
 struct DataReceiver {
+    n_receivers: u32,
+    wait_timeout: Option<std::time::Duration>,
+    connected_at: std::time::Instant,
+    remote_addr: Option<std::net::SocketAddr>,
     res_sender: futures::channel::oneshot::Sender<http::Response<DataReceiverResponseBody>>,
 }
 
 struct Pipe {
     data_sender: Option<DataSender>,
-    data_receiver: Option<DataReceiver>,
+    data_receivers: Vec<DataReceiver>,
+    // Whether the pipe has been removed from the map
+    removed: bool,
 }
 
 impl Pipe {
     fn new() -> Self {
         Self {
             data_sender: None,
-            data_receiver: None,
+            data_receivers: Vec::new(),
+            removed: false,
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.data_sender.is_none() && self.data_receivers.is_empty()
+    }
+
+    // Remove the sender and receivers which have been disconnected while waiting
+    fn remove_disconnected(&mut self, path: &str, status_hub: &StatusHub) {
+        if let Some(data_sender) = self.data_sender.as_mut() {
+            data_sender.read_ahead();
+            if data_sender.is_disconnected() {
+                self.data_sender = None;
+                log::info!("A sender on '{path}' was disconnected while waiting");
+                status_hub.publish(path, StatusEvent::SenderLeft);
+            }
+        }
+        let n_data_receivers = self.data_receivers.len();
+        self.data_receivers
+            .retain(|data_receiver| !data_receiver.res_sender.is_canceled());
+        for _ in self.data_receivers.len()..n_data_receivers {
+            log::info!("A receiver on '{path}' was disconnected while waiting");
+            status_hub.publish(path, StatusEvent::ReceiverLeft);
+        }
+    }
+
+    // Remove the sender and receivers which have waited for the peer longer than their timeouts
+    fn remove_timed_out(&mut self, path: &str, status_hub: &StatusHub) {
+        if let Some(data_sender) = self.data_sender.as_mut() {
+            if is_wait_timed_out(data_sender.wait_timeout, data_sender.connected_at) {
+                // NOTE: try_send() not to block other parties when the sender does not read the response
+                data_sender
+                    .res_event_tx
+                    .try_send(SenderEvent::WaitTimedOut {
+                        wait_timeout: data_sender.wait_timeout.unwrap(),
+                    });
+                self.data_sender = None;
+                log::info!("A sender on '{path}' timed out waiting for receiver(s)");
+                status_hub.publish(path, StatusEvent::SenderLeft);
+            }
+        }
+        let (timed_out_receivers, data_receivers) = std::mem::take(&mut self.data_receivers)
+            .into_iter()
+            .partition(|r| is_wait_timed_out(r.wait_timeout, r.connected_at));
+        self.data_receivers = data_receivers;
+        for data_receiver in timed_out_receivers {
+            let timeout_secs = data_receiver.wait_timeout.unwrap().as_secs_f64();
+            let _ = data_receiver.res_sender.send(receiver_error_response(
+                http::StatusCode::REQUEST_TIMEOUT,
+                format!("No sender connected within {timeout_secs} second(s)."),
+            ));
+            log::info!("A receiver on '{path}' timed out waiting for a sender");
+            status_hub.publish(path, StatusEvent::ReceiverLeft);
+        }
+    }
+
+    // The number of receivers specified by the parties already connected
+    fn n_receivers(&self) -> Option<u32> {
+        match &self.data_sender {
+            Some(data_sender) => Some(data_sender.n_receivers),
+            None => self.data_receivers.first().map(|r| r.n_receivers),
+        }
+    }
+}
+
+/// Request extension which tells whether the request is received over HTTPS
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UsesHttps(pub bool);
+
+/// Request extension which tells the address of the client
+#[derive(Clone, Copy, Debug)]
+pub struct RemoteAddr(pub std::net::SocketAddr);
+
+/// Request extension which tells the identity in the verified client certificate (see util::client_identity())
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Common name (CN) of the subject
+    pub common_name: Option<String>,
+    /// DNS names in the subject alternative names (SAN)
+    pub dns_names: Vec<String>,
+}
+
+// Response extension which tells that the request was rejected
+#[derive(Clone)]
+struct Rejection {
+    reason: String,
+}
+
+/// Response body of Piping Server
+pub type ResponseBody = http_body_util::combinators::UnsyncBoxBody<Bytes, anyhow::Error>;
+
+#[derive(Clone, Debug)]
+pub struct PipingServerConfig {
+    /// Maximum duration for which a sender waits for receivers (no limit if None)
+    pub sender_wait_timeout: Option<std::time::Duration>,
+    /// Maximum duration for which a receiver waits for a sender (no limit if None)
+    pub receiver_wait_timeout: Option<std::time::Duration>,
+    /// Duration without any data moving after which a transfer is aborted (no limit if None)
+    pub idle_transfer_timeout: Option<std::time::Duration>,
+    /// Duration for which an interrupted resumable upload waits for the sender to resume
+    pub upload_resumption_grace_period: std::time::Duration,
+    /// Size of the buffer per transfer to replay the data to a reconnected receiver (disabled if None)
+    /// NOTE: Only a transfer with Content-Length can be resumed
+    pub resume_buffer_size: Option<usize>,
+    /// Duration for which a transfer whose receivers are all disconnected waits for one to reconnect with Range header
+    pub receiver_resumption_grace_period: std::time::Duration,
+    /// Directory to store data sent with "store" query parameter until a receiver connects (disabled if None)
+    pub spool_dir: Option<std::path::PathBuf>,
+    /// Maximum size of data stored by a sender (no limit if None)
+    pub spool_max_file_size: Option<u64>,
+    /// Maximum total size of data in the spool directory (no limit if None)
+    pub spool_max_total_size: Option<u64>,
+    /// Maximum duration for which data is stored (no limit if None)
+    pub spool_max_ttl: Option<std::time::Duration>,
+    /// Value of Access-Control-Allow-Origin header (CORS headers are not sent if None)
+    pub cors_allow_origin: Option<http::HeaderValue>,
+    /// Paths where neither senders nor receivers can connect in addition to the built-in reserved paths
+    pub additional_reserved_paths: Vec<String>,
+    /// The number of chunks buffered for each receiver in addition to the chunk being sent
+    pub channel_buffer_size: usize,
+    /// HTML of the index page (the built-in page if None)
+    pub index_html: Option<String>,
+    /// Text of the help page, where "{base_url}" is replaced with the URL of the server (the built-in page if None)
+    pub help_text: Option<String>,
+}
+
+impl Default for PipingServerConfig {
+    fn default() -> Self {
+        PipingServerConfig {
+            sender_wait_timeout: None,
+            receiver_wait_timeout: None,
+            idle_transfer_timeout: None,
+            upload_resumption_grace_period: std::time::Duration::from_secs(60),
+            resume_buffer_size: None,
+            receiver_resumption_grace_period: std::time::Duration::from_secs(60),
+            spool_dir: None,
+            spool_max_file_size: None,
+            spool_max_total_size: None,
+            spool_max_ttl: None,
+            cors_allow_origin: Some(http::HeaderValue::from_static("*")),
+            additional_reserved_paths: Vec::new(),
+            channel_buffer_size: 1,
+            index_html: None,
+            help_text: None,
         }
     }
 }
 
 pub struct PipingServer {
-    path_to_pipe: Arc<dashmap::DashMap<String, futures::lock::Mutex<Pipe>>>,
+    path_to_pipe: Arc<dashmap::DashMap<String, Arc<futures::lock::Mutex<Pipe>>>>,
+    config: Arc<PipingServerConfig>,
+    status_hub: StatusHub,
+    // Upload token to the ongoing transfer whose upload can be resumed
+    uploads: Arc<dashmap::DashMap<String, ResumableUpload>>,
+    // Path to the ongoing transfer which a reconnected receiver can resume
+    replayable_transfers:
+        Arc<dashmap::DashMap<String, futures::channel::mpsc::Sender<ReceiverReattachment>>>,
+    spool: Option<Spool>,
+    event_listener: Option<Arc<dyn PipingEventListener>>,
+    authorizer: Option<Arc<dyn Authorizer>>,
 }
 
 impl Clone for PipingServer {
     fn clone(&self) -> Self {
         PipingServer {
             path_to_pipe: Arc::clone(&self.path_to_pipe),
+            config: Arc::clone(&self.config),
+            status_hub: self.status_hub.clone(),
+            uploads: Arc::clone(&self.uploads),
+            replayable_transfers: Arc::clone(&self.replayable_transfers),
+            spool: self.spool.clone(),
+            event_listener: self.event_listener.clone(),
+            authorizer: self.authorizer.clone(),
         }
     }
 }
 
+impl Default for PipingServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PipingServer {
     pub fn new() -> Self {
-        PipingServer {
+        Self::with_config(PipingServerConfig::default())
+    }
+
+    /// Same as try_with_config() but panics if the spool directory cannot be opened, which never happens without spool_dir
+    pub fn with_config(config: PipingServerConfig) -> Self {
+        Self::try_with_config(config).expect("failed to create Piping Server")
+    }
+
+    /// Create Piping Server with the config, which fails if the spool directory cannot be opened
+    pub fn try_with_config(config: PipingServerConfig) -> anyhow::Result<Self> {
+        let spool = match &config.spool_dir {
+            Some(spool_dir) => {
+                let spool = Spool::open(
+                    spool_dir.clone(),
+                    SpoolLimits {
+                        max_file_size: config.spool_max_file_size,
+                        max_total_size: config.spool_max_total_size,
+                        max_ttl: config.spool_max_ttl,
+                    },
+                )?;
+                // NOTE: Expired data is also removed when it is accessed
+                if tokio::runtime::Handle::try_current().is_ok() {
+                    spool.spawn_sweeper();
+                }
+                Some(spool)
+            }
+            None => None,
+        };
+        Ok(PipingServer {
             path_to_pipe: Arc::new(dashmap::DashMap::new()),
+            config: Arc::new(config),
+            status_hub: StatusHub::default(),
+            uploads: Arc::new(dashmap::DashMap::new()),
+            replayable_transfers: Arc::new(dashmap::DashMap::new()),
+            spool,
+            event_listener: None,
+            authorizer: None,
+        })
+    }
+
+    /// Register the authorizer consulted before senders and receivers are paired
+    pub fn with_authorizer(mut self, authorizer: impl Authorizer + 'static) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// Register the listener of events in the lifecycle of transfers
+    pub fn with_event_listener(
+        mut self,
+        event_listener: impl PipingEventListener + 'static,
+    ) -> Self {
+        self.event_listener = Some(Arc::new(event_listener));
+        self
+    }
+
+    fn notify_event_listener(&self, notify: impl FnOnce(&dyn PipingEventListener)) {
+        if let Some(event_listener) = &self.event_listener {
+            notify(event_listener.as_ref());
+        }
+    }
+
+    /// The number of paths where a sender or receivers are waiting
+    pub fn n_pipes(&self) -> usize {
+        self.path_to_pipe.len()
+    }
+
+    // Lock the pipe on the path, creating a new one if not found
+    async fn lock_pipe(&self, path: &str) -> futures::lock::OwnedMutexGuard<Pipe> {
+        loop {
+            // NOTE: The map is not locked while waiting for the pipe lock
+            let pipe_mutex = Arc::clone(&self.path_to_pipe.entry(path.to_owned()).or_insert_with(
+                || {
+                    let pipe_mutex = Arc::new(futures::lock::Mutex::new(Pipe::new()));
+                    tokio::spawn(
+                        self.clone()
+                            .watch_pipe(path.to_owned(), Arc::clone(&pipe_mutex)),
+                    );
+                    pipe_mutex
+                },
+            ));
+            let pipe_guard = pipe_mutex.lock_owned().await;
+            // Retry because the pipe might be removed while waiting for the lock
+            if !pipe_guard.removed {
+                return pipe_guard;
+            }
+        }
+    }
+
+    // Periodically remove disconnected or timed-out parties from the pipe until the pipe is removed
+    async fn watch_pipe(self, path: String, pipe_mutex: Arc<futures::lock::Mutex<Pipe>>) {
+        loop {
+            tokio::time::sleep(WAITING_CHECK_INTERVAL).await;
+            let mut pipe_guard = pipe_mutex.lock().await;
+            if pipe_guard.removed {
+                return;
+            }
+            pipe_guard.remove_disconnected(&path, &self.status_hub);
+            pipe_guard.remove_timed_out(&path, &self.status_hub);
+            self.remove_pipe_if_idle(&path, &mut pipe_guard);
+        }
+    }
+
+    // Current state of the path for a status watcher
+    async fn status_state(&self, path: &str) -> StatusEvent {
+        let pipe_mutex = self
+            .path_to_pipe
+            .get(path)
+            .map(|pipe_mutex| Arc::clone(&pipe_mutex));
+        let (sender_waiting, n_waiting_receivers) = match pipe_mutex {
+            Some(pipe_mutex) => {
+                let pipe_guard = pipe_mutex.lock().await;
+                if pipe_guard.removed {
+                    (false, 0)
+                } else {
+                    (
+                        pipe_guard.data_sender.is_some(),
+                        pipe_guard.data_receivers.len() as u32,
+                    )
+                }
+            }
+            None => (false, 0),
+        };
+        StatusEvent::State {
+            sender_waiting,
+            n_waiting_receivers,
+        }
+    }
+
+    fn is_additional_reserved_path(&self, path: &str) -> bool {
+        self.config
+            .additional_reserved_paths
+            .iter()
+            .any(|reserved_path| reserved_path == path)
+    }
+
+    // Remove the pipe from the map if no one is waiting on it
+    // NOTE: A pipe is removed only while it is locked, so a pipe not marked as removed is in the map
+    fn remove_pipe_if_idle(&self, path: &str, pipe: &mut Pipe) {
+        if pipe.is_idle() {
+            pipe.removed = true;
+            self.path_to_pipe.remove(path);
+        }
+    }
+
+    // TTL of the data to store specified by "store" query parameter
+    fn get_store_ttl_result(
+        &self,
+        store: &str,
+        query_params: &HashMap<String, String>,
+    ) -> Result<std::time::Duration, String> {
+        let Some(spool) = self.spool.as_ref() else {
+            return Err("Storing data is not enabled on this server.".to_owned());
+        };
+        let ttl = parse_duration(store)
+            .ok()
+            .filter(|ttl| !ttl.is_zero())
+            .ok_or_else(|| "Invalid \"store\" query parameter".to_owned())?;
+        if get_n_receivers_result(query_params).ok() != Some(1) {
+            return Err("Stored data can be received only by one receiver.".to_owned());
+        }
+        Ok(match spool.max_ttl() {
+            Some(max_ttl) => ttl.min(max_ttl),
+            None => ttl,
+        })
+    }
+
+    // Store the sender's data in the spool until a receiver connects
+    async fn store(
+        &self,
+        path: &str,
+        ttl: std::time::Duration,
+        sender_event_format: SenderEventFormat,
+        req_headers: http::HeaderMap,
+        req_body: RequestBody,
+    ) -> http::Response<Bytes> {
+        let reject = |status: http::StatusCode, message: String| {
+            let mut res = sender_rejection_response(sender_event_format, message, |bytes| bytes);
+            *res.status_mut() = status;
+            res
+        };
+        // NOTE: The spool is enabled because the TTL is valid
+        let spool = self.spool.as_ref().unwrap();
+        let transfer_request =
+            match get_transfer_request(&req_headers, ReadAheadBody::new(req_body)).await {
+                Ok(transfer_request) => transfer_request,
+                Err(err) => {
+                    return reject(http::StatusCode::BAD_REQUEST, format!("{err}"));
+                }
+            };
+        let to_string = |value: Option<http::HeaderValue>| {
+            value.and_then(|value| value.to_str().ok().map(|value| value.to_owned()))
+        };
+        let content_length: Option<u64> =
+            to_string(transfer_request.content_length).and_then(|value| value.parse().ok());
+        let metadata = SpoolMetadata {
+            content_type: to_string(transfer_request.content_type),
+            content_disposition: to_string(transfer_request.content_disposition),
+            x_pipings: req_headers
+                .get_all("x-piping")
+                .iter()
+                .filter_map(|value| value.to_str().ok().map(|value| value.to_owned()))
+                .collect(),
+        };
+        match spool
+            .store(path, ttl, metadata, content_length, transfer_request.body)
+            .await
+        {
+            Ok(n_bytes) => {
+                log::info!(
+                    "Stored {n_bytes} bytes on '{path}' for {} second(s)",
+                    ttl.as_secs()
+                );
+                http::Response::builder()
+                    .header("Content-Type", sender_event_format.content_type())
+                    .body(SenderEvent::Stored { n_bytes, ttl }.to_bytes(sender_event_format))
+                    .unwrap()
+            }
+            Err(SpoolStoreError::AlreadyStored) => reject(
+                http::StatusCode::BAD_REQUEST,
+                format!("Data has been stored on '{path}' already."),
+            ),
+            Err(SpoolStoreError::TooLarge { max_file_size }) => reject(
+                http::StatusCode::PAYLOAD_TOO_LARGE,
+                format!("The data exceeds the limit of {max_file_size} bytes."),
+            ),
+            Err(SpoolStoreError::Full) => reject(
+                http::StatusCode::INSUFFICIENT_STORAGE,
+                "The spool of the server is full.".to_owned(),
+            ),
+            Err(SpoolStoreError::Failed(err)) => {
+                log::info!("Failed to store data on '{path}': {err:?}");
+                reject(
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to store the data.".to_owned(),
+                )
+            }
         }
     }
 
     pub async fn handle(
         self,
         uses_https: bool,
-        req: http::Request<hyper::body::Incoming>,
+        mut req: http::Request<hyper::body::Incoming>,
+    ) -> anyhow::Result<http::Response<ResponseBody>> {
+        req.extensions_mut().insert(UsesHttps(uses_https));
+        self.handle_request(req).await
+    }
+
+    /// Handle a request with any body, where UsesHttps in the request extensions tells whether HTTPS is used
+    pub async fn handle_request<B>(
+        self,
+        req: http::Request<B>,
+    ) -> anyhow::Result<http::Response<ResponseBody>>
+    where
+        B: http_body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let uses_https = req
+            .extensions()
+            .get::<UsesHttps>()
+            .map_or(false, |uses_https| uses_https.0);
+        let req = req.map(|body| {
+            body.map_frame(|frame| {
+                frame.map_data(|mut data| {
+                    use hyper::body::Buf as _;
+                    data.copy_to_bytes(data.remaining())
+                })
+            })
+            .map_err(|err| anyhow!(err.into()))
+            .boxed_unsync()
+        });
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let remote_addr = req
+            .extensions()
+            .get::<RemoteAddr>()
+            .map(|remote_addr| remote_addr.0);
+        let piping_server = self.clone();
+        let mut res = self.respond(uses_https, req).await?;
+        if let Some(Rejection { reason }) = res.extensions_mut().remove::<Rejection>() {
+            piping_server.notify_event_listener(|event_listener| {
+                event_listener.on_rejected(&RejectionEvent {
+                    method,
+                    path,
+                    remote_addr,
+                    status: res.status(),
+                    reason,
+                })
+            });
+        }
+        set_cors_headers(&piping_server.config, res.headers_mut());
+        Ok(res.map(|body| body.boxed_unsync()))
+    }
+
+    /// Send data to the path in process as a sender, where the path can have query parameters except "format" (e.g. "/mypath?n=2")
+    /// The returned future completes when the data is delivered and fails when the sender is rejected or the transfer is aborted
+    pub async fn send<S, E>(
+        &self,
+        path: &str,
+        headers: http::HeaderMap,
+        stream: S,
+    ) -> anyhow::Result<()>
+    where
+        S: futures::Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+    {
+        let mut req = http::Request::builder()
+            .method(http::Method::POST)
+            .uri(path)
+            .body(http_body_util::StreamBody::new(
+                stream.map_ok(http_body::Frame::data),
+            ))?;
+        *req.headers_mut() = headers;
+        // Events are read in NDJSON to know how the transfer ended
+        req.headers_mut().insert(
+            http::header::ACCEPT,
+            http::HeaderValue::from_static("application/x-ndjson"),
+        );
+        let mut res_body = self.clone().handle_request(req).await?.into_body();
+        let mut last_event: Option<serde_json::Value> = None;
+        // NOTE: The events should be read until the end, otherwise the transfer is blocked
+        while let Some(frame) = res_body.frame().await {
+            let Ok(data) = frame?.into_data() else {
+                continue;
+            };
+            for line in data.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+                last_event = Some(serde_json::from_slice(line)?);
+            }
+        }
+        let Some(last_event) = last_event else {
+            anyhow::bail!("no event was received on '{path}'");
+        };
+        match last_event["event"].as_str() {
+            Some("finished") | Some("stored") => Ok(()),
+            _ => Err(anyhow!(
+                "{}",
+                last_event["message"].as_str().unwrap_or_default()
+            )),
+        }
+    }
+
+    /// Receive data from the path in process as a receiver, where the path can have query parameters (e.g. "/mypath?n=2")
+    /// The returned future completes with the headers for the receiver when the transfer starts
+    pub async fn receive(
+        &self,
+        path: &str,
+    ) -> anyhow::Result<(
+        http::HeaderMap,
+        impl futures::Stream<Item = anyhow::Result<Bytes>>,
+    )> {
+        let req = http::Request::builder()
+            .method(http::Method::GET)
+            .uri(path)
+            .body(empty_body::<std::convert::Infallible>())?;
+        let (res_parts, res_body) = self.clone().handle_request(req).await?.into_parts();
+        if !res_parts.status.is_success() {
+            let message = res_body.collect().await?.to_bytes();
+            anyhow::bail!("{}", String::from_utf8_lossy(&message).trim_end());
+        }
+        let stream = http_body_util::BodyStream::new(res_body)
+            .try_filter_map(|frame| futures::future::ok(frame.into_data().ok()));
+        Ok((res_parts.headers, stream))
+    }
+
+    async fn respond(
+        self,
+        uses_https: bool,
+        req: http::Request<RequestBody>,
     ) -> anyhow::Result<http::Response<impl http_body::Body<Data = Bytes, Error = anyhow::Error>>>
     {
-        seq_macro::seq!(N in 1..=2 {
+        seq_macro::seq!(N in 1..=4 {
             #[derive(Debug)]
             #[auto_enums::enum_derive(http_body1::Body)]
             enum BodyEnum<D, E, Full, Empty, #(B~N,)*> {
@@ -120,6 +797,10 @@ impl PipingServer {
 
         let (req_parts, req_body) = req.into_parts();
         let path = req_parts.uri.path();
+        let remote_addr = req_parts
+            .extensions
+            .get::<RemoteAddr>()
+            .map(|remote_addr| remote_addr.0);
         let path_and_query: &str = req_parts
             .uri
             .path_and_query()
@@ -129,18 +810,60 @@ impl PipingServer {
         log::info!(
             "{} {} {:?}",
             req_parts.method,
-            path_and_query,
+            redact_credential_query_params(path_and_query),
             req_parts.version,
         );
 
+        let query_params = query_param_to_hash_map(req_parts.uri.query());
+        // NOTE: Preflight requests and the built-in pages are not authorized
+        let is_public = req_parts.method == http::Method::OPTIONS
+            || ((req_parts.method == http::Method::GET || req_parts.method == http::Method::HEAD)
+                && reserved_paths::VALUES.contains(&path));
+        let rewritten_path: Option<String> = match &self.authorizer {
+            Some(authorizer) if !is_public => {
+                let authorization = authorizer.authorize(&AuthorizationRequest {
+                    method: &req_parts.method,
+                    path,
+                    headers: &req_parts.headers,
+                    query_params: &query_params,
+                    remote_addr,
+                    client_identity: req_parts.extensions.get::<ClientIdentity>(),
+                });
+                match authorization {
+                    Authorization::Allow => None,
+                    Authorization::RewritePath(rewritten_path) => Some(rewritten_path),
+                    Authorization::Deny { status, message } => {
+                        log::info!("{} {path} was denied: {message}", req_parts.method);
+                        let mut res = rejection_response(message, |bytes| {
+                            BodyEnum::FullBody(full_body(bytes))
+                        });
+                        *res.status_mut() = status;
+                        if status == http::StatusCode::UNAUTHORIZED {
+                            if let Some(www_authenticate) = authorizer.www_authenticate() {
+                                res.headers_mut()
+                                    .insert(http::header::WWW_AUTHENTICATE, www_authenticate);
+                            }
+                        }
+                        return Ok(res);
+                    }
+                }
+            }
+            _ => None,
+        };
+        let path: &str = rewritten_path.as_deref().unwrap_or(path);
+
         if req_parts.method == http::Method::GET || req_parts.method == http::Method::HEAD {
             match path {
                 reserved_paths::INDEX => {
                     return Ok(http::Response::builder()
                         .status(200)
                         .header("Content-Type", "text/html")
-                        .header("Access-Control-Allow-Origin", "*")
-                        .body(BodyEnum::FullBody(full_body(&**dynamic_resources::INDEX)))
+                        .body(BodyEnum::FullBody(full_body(
+                            match &self.config.index_html {
+                                Some(index_html) => Bytes::from(index_html.clone()),
+                                None => Bytes::from_static(dynamic_resources::INDEX.as_bytes()),
+                            },
+                        )))
                         .unwrap());
                 }
                 reserved_paths::NO_SCRIPT => {
@@ -155,7 +878,6 @@ impl PipingServer {
                     return Ok(http::Response::builder()
                         .status(200)
                         .header("Content-Type", "text/html")
-                        .header("Access-Control-Allow-Origin", "*")
                         .header(
                             "Content-Security-Policy",
                             format!("default-src 'none'; style-src 'nonce-{style_nonce}'"),
@@ -168,7 +890,6 @@ impl PipingServer {
                     return Ok(http::Response::builder()
                         .status(200)
                         .header("Content-Type", "text/plain")
-                        .header("Access-Control-Allow-Origin", "*")
                         .body(BodyEnum::FullBody(full_body(format!("{version} (Rust)\n"))))
                         .unwrap());
                 }
@@ -191,11 +912,13 @@ impl PipingServer {
                     };
                     let base_url = Url::parse(format!("{schema}://{host}").as_str())
                         .unwrap_or_else(|_| "http://hostname/".parse().unwrap());
-                    let help = dynamic_resources::help(&base_url);
+                    let help = match &self.config.help_text {
+                        Some(help_text) => help_text.replace("{base_url}", base_url.as_str()),
+                        None => dynamic_resources::help(&base_url),
+                    };
                     return Ok(http::Response::builder()
                         .status(200)
                         .header("Content-Type", "text/plain")
-                        .header("Access-Control-Allow-Origin", "*")
                         .body(BodyEnum::FullBody(full_body(help)))
                         .unwrap());
                 }
@@ -215,72 +938,282 @@ impl PipingServer {
                 }
                 _ => {}
             }
+            if self.is_additional_reserved_path(path) {
+                return Ok(http::Response::builder()
+                    .status(404)
+                    .header("Content-Length", 0)
+                    .body(BodyEnum::EmptyBody(empty_body()))
+                    .unwrap());
+            }
+            if let Some(watched_path) = path.strip_prefix(STATUS_PATH_PREFIX) {
+                if watched_path.starts_with('/') {
+                    let events = self.status_hub.subscribe(watched_path);
+                    let state = self.status_state(watched_path).await;
+                    return Ok(http::Response::builder()
+                        .status(200)
+                        .header("Content-Type", "text/event-stream")
+                        .header("Cache-Control", "no-cache")
+                        .body(BodyEnum::Body3(http_body_util::StreamBody::new(
+                            futures::stream::iter([Ok(http_body::Frame::data(
+                                state.to_sse_bytes(),
+                            ))])
+                            .chain(events),
+                        )))
+                        .unwrap());
+                }
+            }
+        }
+
+        // Resumable upload (ref: https://datatracker.ietf.org/doc/draft-ietf-httpbis-resumable-upload/)
+        if let Some(upload_token) = query_params.get("upload") {
+            match req_parts.method {
+                // Upload offset discovery
+                http::Method::HEAD => {
+                    let Some(offset) = self
+                        .uploads
+                        .get(upload_token)
+                        .filter(|upload| upload.path == path)
+                        .map(|upload| upload.n_transferred_bytes.load(Ordering::Relaxed))
+                    else {
+                        return Ok(http::Response::builder()
+                            .status(404)
+                            .body(BodyEnum::EmptyBody(empty_body()))
+                            .unwrap());
+                    };
+                    return Ok(http::Response::builder()
+                        .status(204)
+                        .header("Upload-Offset", offset)
+                        .header("Upload-Complete", "?0")
+                        .header("Cache-Control", "no-store")
+                        .header(
+                            "Access-Control-Expose-Headers",
+                            "Upload-Offset, Upload-Complete",
+                        )
+                        .body(BodyEnum::EmptyBody(empty_body()))
+                        .unwrap());
+                }
+                // Upload appending
+                http::Method::PATCH | http::Method::PUT | http::Method::POST => {
+                    let sender_event_format =
+                        SenderEventFormat::from_request(&query_params, &req_parts.headers);
+                    let reject = |status: http::StatusCode, message: String| {
+                        let mut res =
+                            sender_rejection_response(sender_event_format, message, |bytes| {
+                                BodyEnum::FullBody(full_body(bytes))
+                            });
+                        *res.status_mut() = status;
+                        res
+                    };
+                    let Ok(Some(offset)) = get_upload_offset_result(&req_parts.headers) else {
+                        return Ok(reject(
+                            http::StatusCode::BAD_REQUEST,
+                            "Upload-Offset or Content-Range should be specified to resume the upload"
+                                .to_owned(),
+                        ));
+                    };
+                    // NOTE: The body completes the upload unless Upload-Complete: ?0 is specified
+                    let Ok(complete) = get_upload_complete_result(&req_parts.headers) else {
+                        return Ok(reject(
+                            http::StatusCode::BAD_REQUEST,
+                            "Upload-Complete should be ?0 or ?1".to_owned(),
+                        ));
+                    };
+                    let resumption_tx = self
+                        .uploads
+                        .get(upload_token)
+                        .filter(|upload| upload.path == path)
+                        .map(|upload| upload.resumption_tx.clone());
+                    let not_found = || {
+                        reject(
+                            http::StatusCode::NOT_FOUND,
+                            format!("The upload to resume was not found on '{path}'."),
+                        )
+                    };
+                    let Some(mut resumption_tx) = resumption_tx else {
+                        return Ok(not_found());
+                    };
+                    let (res_body_tx, res_body_rx) =
+                        futures::channel::mpsc::channel::<
+                            Result<http_body::Frame<Bytes>, anyhow::Error>,
+                        >(self.config.channel_buffer_size);
+                    let (reply_tx, reply_rx) = futures::channel::oneshot::channel();
+                    let resumption = UploadResumption {
+                        offset,
+                        complete: complete.unwrap_or(true),
+                        body: ReadAheadBody::new(req_body),
+                        res_event_tx: SenderEventSender::new(res_body_tx, sender_event_format),
+                        reply_tx,
+                    };
+                    if resumption_tx.send(resumption).await.is_err() {
+                        return Ok(not_found());
+                    }
+                    return match reply_rx.await {
+                        Ok(Ok(())) => Ok(http::Response::builder()
+                            .header("Content-Type", sender_event_format.content_type())
+                            .body(BodyEnum::Body2(http_body_util::StreamBody::new(
+                                res_body_rx,
+                            )))
+                            .unwrap()),
+                        Ok(Err(current_offset)) => {
+                            let mut res = reject(
+                                http::StatusCode::CONFLICT,
+                                format!(
+                                    "The upload offset should be {current_offset} but {offset}."
+                                ),
+                            );
+                            res.headers_mut()
+                                .insert("Upload-Offset", current_offset.into());
+                            Ok(res)
+                        }
+                        Err(_) => Ok(not_found()),
+                    };
+                }
+                _ => {}
+            }
         }
 
         match req_parts.method {
             http::Method::GET => {
+                let reject = |message: String| {
+                    rejection_response(message, |bytes| BodyEnum::FullBody(full_body(bytes)))
+                };
                 if let Some(value) = req_parts.headers.get("service-worker") {
                     if value == http::HeaderValue::from_static("script") {
                         // Reject Service Worker registration
-                        return Ok(rejection_response(BodyEnum::FullBody(full_body(
-                            "[ERROR] Service Worker registration is rejected.\n",
-                        ))));
+                        return Ok(reject(
+                            "Service Worker registration is rejected.".to_owned(),
+                        ));
+                    }
+                }
+                // A reconnected receiver resumes the ongoing transfer
+                if let Some(offset) = get_range_start(&req_parts.headers) {
+                    let reattachment_tx = self
+                        .replayable_transfers
+                        .get(path)
+                        .map(|reattachment_tx| reattachment_tx.clone());
+                    if let Some(mut reattachment_tx) = reattachment_tx {
+                        let (res_sender, res_receiver) = futures::channel::oneshot::channel();
+                        let reattachment = ReceiverReattachment { offset, res_sender };
+                        if reattachment_tx.send(reattachment).await.is_ok() {
+                            if let Ok(res) = res_receiver.await {
+                                let (res_parts, res_body) = res.into_parts();
+                                return Ok(http::Response::from_parts(
+                                    res_parts,
+                                    BodyEnum::Body1(res_body),
+                                ));
+                            }
+                        }
+                    }
+                }
+                // Stored data is handed to the first receiver
+                if let Some(entry) = self.spool.as_ref().and_then(|spool| spool.take(path)) {
+                    let spool = self.spool.as_ref().unwrap();
+                    let res_builder = http::Response::builder()
+                        .option_header("Content-Type", entry.content_type.clone())
+                        .header("Content-Length", entry.size)
+                        .option_header("Content-Disposition", entry.content_disposition.clone())
+                        .header_values(
+                            "X-Piping",
+                            entry
+                                .x_pipings
+                                .iter()
+                                .filter_map(|value| value.parse().ok()),
+                        )
+                        .option_header(
+                            "Access-Control-Expose-Headers",
+                            if entry.x_pipings.is_empty() {
+                                None
+                            } else {
+                                Some("X-Piping")
+                            },
+                        )
+                        .header("X-Robots-Tag", "none");
+                    match spool.read(path, entry).await {
+                        Ok(stream) => {
+                            log::info!("Stored data on '{path}' is sent to a receiver");
+                            return Ok(res_builder
+                                .body(BodyEnum::Body4(http_body_util::StreamBody::new(stream)))
+                                .unwrap());
+                        }
+                        Err(err) => {
+                            log::error!("Failed to read stored data on '{path}': {err:?}");
+                        }
                     }
                 }
-                let query_params = query_param_to_hash_map(req_parts.uri.query());
                 let Ok(n_receivers): Result<u32, _> = get_n_receivers_result(&query_params) else {
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(
-                        "[ERROR] Invalid \"n\" query parameter\n",
-                    ))));
+                    return Ok(reject("Invalid \"n\" query parameter".to_owned()));
                 };
-                if n_receivers <= 0 {
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(format!(
-                        "[ERROR] n should > 0, but n = {n_receivers}.\n"
-                    )))));
-                }
-                if n_receivers > 1 {
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(
-                        "[ERROR] n > 1 not supported yet.\n",
-                    ))));
-                }
-                let pipe_mutex = self
-                    .path_to_pipe
-                    .entry(path.to_owned())
-                    .or_insert_with(|| futures::lock::Mutex::new(Pipe::new()));
-                let mut pipe_guard = pipe_mutex.lock().await;
-                let receiver_connected: bool = (&pipe_guard.data_receiver).is_some();
-                // If a receiver has been connected already
-                if receiver_connected {
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(format!(
-                        "[ERROR] Another receiver has been connected on '{path}'.\n",
-                    )))));
+                if n_receivers == 0 {
+                    return Ok(reject(format!("n should > 0, but n = {n_receivers}.")));
+                }
+                let Ok(wait_timeout) =
+                    get_wait_timeout_result(&query_params, self.config.receiver_wait_timeout)
+                else {
+                    return Ok(reject("Invalid \"timeout\" query parameter".to_owned()));
+                };
+                let mut pipe_guard = self.lock_pipe(path).await;
+                pipe_guard.remove_disconnected(path, &self.status_hub);
+                // If the number of receivers is different from the one already connected
+                if let Some(expected_n_receivers) = pipe_guard.n_receivers() {
+                    if expected_n_receivers != n_receivers {
+                        return Ok(reject(format!(
+                            "The number of receivers should be {expected_n_receivers} but {n_receivers}.",
+                        )));
+                    }
+                }
+                // If all receivers have been connected already
+                if pipe_guard.data_receivers.len() as u32 >= n_receivers {
+                    return Ok(reject(format!(
+                        "The number of receivers has reached limits on '{path}'."
+                    )));
                 }
                 let (res_sender, res_receiver) = futures::channel::oneshot::channel::<
                     http::Response<DataReceiverResponseBody>,
                 >();
-                match pipe_guard.data_sender.take() {
-                    // If sender is found
-                    Some(mut data_sender) => {
-                        data_sender
-                            .res_body_tx
-                            .send(Ok(http_body::Frame::data(Bytes::from(
-                                "[INFO] A receiver was connected.\n",
-                            ))))
-                            .await
-                            .unwrap();
-                        transfer(path.to_string(), data_sender, DataReceiver { res_sender })
-                            .await
-                            .unwrap();
-                    }
-                    // If sender is not found
-                    None => {
-                        pipe_guard
-                            .data_receiver
-                            .replace(DataReceiver { res_sender });
+                pipe_guard.data_receivers.push(DataReceiver {
+                    n_receivers,
+                    wait_timeout,
+                    connected_at: std::time::Instant::now(),
+                    remote_addr,
+                    res_sender,
+                });
+                let n_connected_receivers = pipe_guard.data_receivers.len() as u32;
+                self.status_hub.publish(
+                    path,
+                    StatusEvent::ReceiverWaiting {
+                        n_receivers,
+                        n_waiting_receivers: n_connected_receivers,
+                    },
+                );
+                self.notify_event_listener(|event_listener| {
+                    event_listener.on_receiver_waiting(&WaitingEvent {
+                        path: path.to_owned(),
+                        n_receivers,
+                        remote_addr,
+                    })
+                });
+                if let Some(data_sender) = pipe_guard.data_sender.as_mut() {
+                    data_sender
+                        .res_event_tx
+                        .send(SenderEvent::ReceiverConnected {
+                            n_connected_receivers,
+                        })
+                        .await;
+                }
+                // If the sender and all receivers are connected
+                if pipe_guard.data_sender.is_some()
+                    && pipe_guard.data_receivers.len() as u32 == n_receivers
+                {
+                    let data_sender = pipe_guard.data_sender.take().unwrap();
+                    let data_receivers = std::mem::take(&mut pipe_guard.data_receivers);
+                    if let Err(err) =
+                        transfer(self.clone(), path.to_string(), data_sender, data_receivers).await
+                    {
+                        log::error!("Failed to start transfer on '{path}': {err:?}");
                     }
-                };
+                }
+                self.remove_pipe_if_idle(path, &mut pipe_guard);
                 drop(pipe_guard);
-                drop(pipe_mutex);
                 let (res_parts, res_body) = res_receiver.await?.into_parts();
                 Ok(http::Response::from_parts(
                     res_parts,
@@ -288,96 +1221,179 @@ impl PipingServer {
                 ))
             }
             http::Method::POST | http::Method::PUT => {
-                if reserved_paths::VALUES.contains(&path) {
+                let sender_event_format =
+                    SenderEventFormat::from_request(&query_params, &req_parts.headers);
+                let reject = |message: String| {
+                    sender_rejection_response(sender_event_format, message, |bytes| {
+                        BodyEnum::FullBody(full_body(bytes))
+                    })
+                };
+                if reserved_paths::VALUES.contains(&path)
+                    || path.starts_with(&format!("{STATUS_PATH_PREFIX}/"))
+                    || self.is_additional_reserved_path(path)
+                {
                     // Reject reserved path sending
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(Bytes::from(format!("[ERROR] Cannot send to the reserved path '{path}'. (e.g. '/mypath123')\n"))))));
+                    return Ok(reject(format!(
+                        "Cannot send to the reserved path '{path}'. (e.g. '/mypath123')"
+                    )));
                 }
-                // Notify that Content-Range is not supported
-                // In the future, resumable upload using Content-Range might be supported
-                // ref: https://github.com/httpwg/http-core/pull/653
-                if req_parts.headers.contains_key("content-range") {
-                    // Reject reserved path sending
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(
-                        Bytes::from(format!(
-                            "[ERROR] Content-Range is not supported for now in {}\n",
-                            req_parts.method,
-                        )),
-                    ))));
-                }
-                let query_params = query_param_to_hash_map(req_parts.uri.query());
+                // Content-Range is supported only to resume an upload specified by "upload" query parameter
+                if req_parts.headers.contains_key("content-range")
+                    || req_parts.headers.contains_key("upload-offset")
+                {
+                    return Ok(reject(format!(
+                        "Content-Range is supported only to resume an upload in {}",
+                        req_parts.method,
+                    )));
+                }
+                let store_ttl = match query_params.get("store") {
+                    Some(store) => match self.get_store_ttl_result(store, &query_params) {
+                        Ok(ttl) => Some(ttl),
+                        Err(message) => return Ok(reject(message)),
+                    },
+                    None => None,
+                };
                 let Ok(n_receivers): Result<u32, _> = get_n_receivers_result(&query_params) else {
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(
-                        Bytes::from("[ERROR] Invalid \"n\" query parameter\n"),
-                    ))));
+                    return Ok(reject("Invalid \"n\" query parameter".to_owned()));
                 };
-                if n_receivers <= 0 {
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(format!(
-                        "[ERROR] n should > 0, but n = {n_receivers}.\n"
-                    )))));
-                }
-                if n_receivers > 1 {
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(
-                        "[ERROR] n > 1 not supported yet.\n",
-                    ))));
-                }
-                let pipe_mutex = self
-                    .path_to_pipe
-                    .entry(path.to_owned())
-                    .or_insert_with(|| futures::lock::Mutex::new(Pipe::new()));
-                let mut pipe_guard = pipe_mutex.lock().await;
-                let sender_connected: bool = (&pipe_guard.data_sender).is_some();
+                if n_receivers == 0 {
+                    return Ok(reject(format!("n should > 0, but n = {n_receivers}.")));
+                }
+                let Ok(wait_timeout) =
+                    get_wait_timeout_result(&query_params, self.config.sender_wait_timeout)
+                else {
+                    return Ok(reject("Invalid \"timeout\" query parameter".to_owned()));
+                };
+                let Ok(progress_interval) =
+                    get_progress_interval_result(&query_params, &req_parts.headers)
+                else {
+                    return Ok(reject("Invalid progress interval".to_owned()));
+                };
+                let mut pipe_guard = self.lock_pipe(path).await;
+                pipe_guard.remove_disconnected(path, &self.status_hub);
+                // The data is stored unless a receiver is waiting, to whom the data is sent directly
+                if let Some(ttl) = store_ttl {
+                    if pipe_guard.data_receivers.is_empty() {
+                        self.remove_pipe_if_idle(path, &mut pipe_guard);
+                        drop(pipe_guard);
+                        return Ok(self
+                            .store(path, ttl, sender_event_format, req_parts.headers, req_body)
+                            .await
+                            .map(|body| BodyEnum::FullBody(full_body(body))));
+                    }
+                }
                 // If a sender has been connected already
-                if sender_connected {
-                    return Ok(rejection_response(BodyEnum::FullBody(full_body(format!(
-                        "[ERROR] Another sender has been connected on '{path}'.\n"
-                    )))));
+                if pipe_guard.data_sender.is_some() {
+                    return Ok(reject(format!(
+                        "Another sender has been connected on '{path}'."
+                    )));
                 }
+                // If the number of receivers is different from the one already connected
+                if let Some(expected_n_receivers) = pipe_guard.n_receivers() {
+                    if expected_n_receivers != n_receivers {
+                        return Ok(reject(format!(
+                            "The number of receivers should be {expected_n_receivers} but {n_receivers}.",
+                        )));
+                    }
+                }
+
+                let Ok(upload_complete) = get_upload_complete_result(&req_parts.headers) else {
+                    return Ok(reject("Upload-Complete should be ?0 or ?1.".to_owned()));
+                };
 
-                let (mut res_body_tx, res_body_rx) = futures::channel::mpsc::channel::<
+                let (res_body_tx, res_body_rx) = futures::channel::mpsc::channel::<
                     Result<http_body::Frame<Bytes>, anyhow::Error>,
-                >(1);
-
-                match pipe_guard.data_receiver.take() {
-                    // If receiver is found
-                    Some(data_receiver) => {
-                        res_body_tx
-                            .send(Ok(http_body::Frame::data(Bytes::from(
-                                "[INFO] 1 receiver(s) has/have been connected.\n",
-                            ))))
-                            .await
-                            .unwrap();
-                        transfer(
-                            path.to_string(),
-                            DataSender {
-                                req_headers: req_parts.headers,
-                                req_body,
-                                res_body_tx,
-                            },
-                            data_receiver,
-                        )
-                        .await
-                        .unwrap();
+                >(self.config.channel_buffer_size);
+                let mut res_event_tx = SenderEventSender::new(res_body_tx, sender_event_format);
+                // The sender can resume the upload at the upload resource if Upload-Complete is specified
+                let upload_token: Option<String> = if upload_complete.is_some() {
+                    use base64::Engine as _;
+                    let mut token_bytes = [0u8; 16];
+                    getrandom::getrandom(&mut token_bytes).unwrap();
+                    Some(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(token_bytes))
+                } else {
+                    None
+                };
+
+                let n_connected_receivers = pipe_guard.data_receivers.len() as u32;
+                // If all receivers are found
+                if n_connected_receivers == n_receivers {
+                    res_event_tx
+                        .send(SenderEvent::ReceiversConnected { n_receivers })
+                        .await;
+                    let data_receivers = std::mem::take(&mut pipe_guard.data_receivers);
+                    let data_sender = DataSender {
+                        n_receivers,
+                        wait_timeout,
+                        connected_at: std::time::Instant::now(),
+                        progress_interval,
+                        upload_token: upload_token.clone(),
+                        upload_complete: upload_complete.unwrap_or(true),
+                        remote_addr,
+                        req_headers: req_parts.headers,
+                        req_body: ReadAheadBody::new(req_body),
+                        res_event_tx,
+                    };
+                    if let Err(err) =
+                        transfer(self.clone(), path.to_string(), data_sender, data_receivers).await
+                    {
+                        log::error!("Failed to start transfer on '{path}': {err:?}");
                     }
-                    // If receiver is not found
-                    None => {
-                        res_body_tx
-                            .send(Ok(http_body::Frame::data(Bytes::from(
-                                "[INFO] Waiting for 1 receiver(s)...\n",
-                            ))))
-                            .await
-                            .unwrap();
-                        pipe_guard.data_sender.replace(DataSender {
-                            req_headers: req_parts.headers,
-                            req_body,
-                            res_body_tx,
-                        });
+                } else {
+                    res_event_tx
+                        .send(SenderEvent::Waiting { n_receivers })
+                        .await;
+                    if n_connected_receivers > 0 {
+                        res_event_tx
+                            .send(SenderEvent::ReceiversConnected {
+                                n_receivers: n_connected_receivers,
+                            })
+                            .await;
                     }
+                    let mut data_sender = DataSender {
+                        n_receivers,
+                        wait_timeout,
+                        connected_at: std::time::Instant::now(),
+                        progress_interval,
+                        upload_token: upload_token.clone(),
+                        upload_complete: upload_complete.unwrap_or(true),
+                        remote_addr,
+                        req_headers: req_parts.headers,
+                        req_body: ReadAheadBody::new(req_body),
+                        res_event_tx,
+                    };
+                    data_sender.read_ahead();
+                    pipe_guard.data_sender.replace(data_sender);
+                    self.status_hub
+                        .publish(path, StatusEvent::SenderWaiting { n_receivers });
+                    self.notify_event_listener(|event_listener| {
+                        event_listener.on_sender_waiting(&WaitingEvent {
+                            path: path.to_owned(),
+                            n_receivers,
+                            remote_addr,
+                        })
+                    });
                 }
+                self.remove_pipe_if_idle(path, &mut pipe_guard);
                 drop(pipe_guard);
-                drop(pipe_mutex);
+                let has_upload_token = upload_token.is_some();
                 Ok(http::Response::builder()
-                    .header("Content-Type", "text/plain")
-                    .header("Access-Control-Allow-Origin", "*")
+                    .header("Content-Type", sender_event_format.content_type())
+                    .option_header(
+                        "Location",
+                        // NOTE: The path before rewritten by the authorizer
+                        upload_token.map(|upload_token| {
+                            format!("{}?upload={upload_token}", req_parts.uri.path())
+                        }),
+                    )
+                    .option_header(
+                        "Access-Control-Expose-Headers",
+                        if has_upload_token {
+                            Some("Location")
+                        } else {
+                            None
+                        },
+                    )
                     .body(BodyEnum::Body2(http_body_util::StreamBody::new(
                         res_body_rx,
                     )))
@@ -387,14 +1403,13 @@ impl PipingServer {
                 // Response for Preflight request
                 Ok(http::Response::builder()
                     .status(200)
-                    .header("Access-Control-Allow-Origin", "*")
                     .header(
                         "Access-Control-Allow-Methods",
-                        "GET, HEAD, POST, PUT, OPTIONS",
+                        "GET, HEAD, POST, PUT, PATCH, OPTIONS",
                     )
                     .header(
                         "Access-Control-Allow-Headers",
-                        "Content-Type, Content-Disposition, X-Piping",
+                        "Content-Type, Content-Disposition, X-Piping, X-Piping-Progress, Content-Range, Upload-Offset, Upload-Complete",
                     )
                     // Expose "Access-Control-Allow-Headers" for Web browser detecting X-Piping feature
                     .header(
@@ -423,7 +1438,9 @@ impl PipingServer {
                 log::info!("Unsupported method: {}", req_parts.method);
                 Ok(http::Response::builder()
                     .status(405)
-                    .header("Access-Control-Allow-Origin", "*")
+                    .extension(Rejection {
+                        reason: format!("Unsupported method: {}.", req_parts.method),
+                    })
                     .body(BodyEnum::FullBody(full_body(format!(
                         "[ERROR] Unsupported method: {}.\n",
                         req_parts.method
@@ -445,20 +1462,20 @@ impl TransferRequest {
     #[inline]
     fn from_hyper_incoming(
         headers: &http::header::HeaderMap,
-        body: hyper::body::Incoming,
+        body: SenderRequestBody,
     ) -> TransferRequest {
         TransferRequest {
             content_type: headers.get("content-type").cloned(),
             content_length: headers.get("content-length").cloned(),
             content_disposition: headers.get("content-disposition").cloned(),
-            body: TransferRequestBody::Incoming(body.map_err(|e| e.into())),
+            body: TransferRequestBody::Incoming(body),
         }
     }
 }
 
 async fn get_transfer_request(
     headers: &http::header::HeaderMap,
-    body: hyper::body::Incoming,
+    body: SenderRequestBody,
 ) -> anyhow::Result<TransferRequest> {
     let Some(content_type) = headers.get("content-type") else {
         return Ok(TransferRequest::from_hyper_incoming(headers, body));
@@ -471,7 +1488,6 @@ async fn get_transfer_request(
     if mime_type.essence_str() != "multipart/form-data" {
         return Ok(TransferRequest::from_hyper_incoming(headers, body));
     }
-    let body: IncomingMapErrBody = body.map_err(|e| e.into());
     let boundary = mime_type
         .get_param("boundary")
         .map(|b| b.to_string())
@@ -487,14 +1503,11 @@ async fn get_transfer_request(
     );
     let mut multipart_stream = mpart_async::server::MultipartStream::new(boundary, body_stream);
 
-    while let Ok(Some(field)) = multipart_stream.try_next().await {
-        // NOTE: Only first one is transferred
+    // NOTE: Only first one is transferred
+    if let Ok(Some(field)) = multipart_stream.try_next().await {
         let headers = field.headers().clone();
-        let frame_stream: MultipartFieldMapToFrameStream = field.map(|result| {
-            result
-                .map(|b| hyper::body::Frame::data(b))
-                .map_err(|e| e.into())
-        });
+        let frame_stream: MultipartFieldMapToFrameStream =
+            field.map(|result| result.map(hyper::body::Frame::data).map_err(|e| e.into()));
         return Ok(TransferRequest {
             content_type: headers.get("content-type").cloned(),
             content_length: headers.get("content-length").cloned(),
@@ -506,85 +1519,796 @@ async fn get_transfer_request(
 }
 
 async fn transfer(
+    piping_server: PipingServer,
     path: String,
     data_sender: DataSender,
-    data_receiver: DataReceiver,
+    data_receivers: Vec<DataReceiver>,
 ) -> anyhow::Result<()> {
+    let idle_transfer_timeout = piping_server.config.idle_transfer_timeout;
+    let status_hub = piping_server.status_hub.clone();
     let DataSender {
+        progress_interval,
+        upload_token,
+        mut upload_complete,
+        remote_addr: sender_addr,
         req_headers: data_sender_req_headers,
         req_body: data_sender_req_body,
-        res_body_tx: mut data_sender_res_body_tx,
+        res_event_tx: mut data_sender_res_event_tx,
+        ..
     } = data_sender;
     log::info!("Transfer start: '{path}'");
     let transfer_request =
         get_transfer_request(&data_sender_req_headers, data_sender_req_body).await?;
-    // The finish_waiter will tell when the body is finished
-    let (finish_detectable_body, sender_req_body_finish_waiter) =
-        finish_detectable_body(transfer_request.body);
-    let x_piping = data_sender_req_headers.get_all("x-piping");
     let has_x_piping = data_sender_req_headers.contains_key("x-piping");
-    // Create receiver's response
-    let receiver_res = http::Response::builder()
-        .option_header("Content-Type", transfer_request.content_type)
-        .option_header("Content-Length", transfer_request.content_length)
-        .option_header("Content-Disposition", transfer_request.content_disposition)
-        .header_values("X-Piping", x_piping.into_iter().cloned())
-        .header("Access-Control-Allow-Origin", "*")
+    let n_receivers = data_receivers.len();
+    // NOTE: Content-Length of the request with Upload-Complete: ?0 is not the length of the whole upload
+    let content_length: Option<u64> = transfer_request
+        .content_length
+        .as_ref()
+        .filter(|_| upload_complete)
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+    // Headers of receivers' responses except Content-Length
+    let receiver_res_headers = http::Response::builder()
+        .option_header("Content-Type", transfer_request.content_type.clone())
+        .option_header(
+            "Content-Disposition",
+            transfer_request.content_disposition.clone(),
+        )
+        .header_values(
+            "X-Piping",
+            data_sender_req_headers
+                .get_all("x-piping")
+                .into_iter()
+                .cloned(),
+        )
         .option_header(
             "Access-Control-Expose-Headers",
             if has_x_piping { Some("X-Piping") } else { None },
         )
         .header("X-Robots-Tag", "none")
-        .body(finish_detectable_body)
-        .unwrap();
-    // Return response to receiver
-    data_receiver
-        .res_sender
-        .send(receiver_res)
-        .map_err(|_| anyhow!("Failed to respond to receiver"))?;
+        .body(())
+        .unwrap()
+        .into_parts()
+        .0
+        .headers;
+    let mut receiver_body_senders = Vec::with_capacity(n_receivers);
+    // Bytes delivered to the receivers disconnected halfway, which are replaced by reconnected receivers
+    let mut disconnected_receivers_n_bytes: Vec<u64> = Vec::new();
+    let mut receiver_addrs = Vec::with_capacity(n_receivers);
+    for data_receiver in data_receivers {
+        let (receiver_body_sender, receiver_body) =
+            receiver_body_channel(piping_server.config.channel_buffer_size, content_length);
+        // Create receiver's response
+        let mut receiver_res = http::Response::new(receiver_body);
+        *receiver_res.headers_mut() = receiver_res_headers.clone();
+        if let Some(content_length) = content_length {
+            receiver_res
+                .headers_mut()
+                .insert(http::header::CONTENT_LENGTH, content_length.into());
+        }
+        // Return response to receiver
+        if data_receiver.res_sender.send(receiver_res).is_err() {
+            log::info!("A receiver on '{path}' was disconnected before transfer");
+            disconnected_receivers_n_bytes.push(0);
+            continue;
+        }
+        receiver_body_senders.push(receiver_body_sender);
+        receiver_addrs.push(data_receiver.remote_addr);
+    }
+    piping_server.notify_event_listener(|event_listener| {
+        event_listener.on_transfer_start(&TransferStartEvent {
+            path: path.clone(),
+            headers: {
+                let mut headers = data_sender_req_headers.clone();
+                headers.remove(http::header::AUTHORIZATION);
+                headers.remove(http::header::PROXY_AUTHORIZATION);
+                headers
+            },
+            sender_addr,
+            receiver_addrs,
+        })
+    });
+
+    let n_transferred_bytes = Arc::new(AtomicU64::new(0));
+    // Only a raw request body can be resumed
+    let mut resumption_rx = match (&upload_token, &transfer_request.body) {
+        (Some(upload_token), TransferRequestBody::Incoming(_)) => {
+            let (resumption_tx, resumption_rx) = futures::channel::mpsc::channel(1);
+            piping_server.uploads.insert(
+                upload_token.clone(),
+                ResumableUpload {
+                    path: path.clone(),
+                    n_transferred_bytes: Arc::clone(&n_transferred_bytes),
+                    resumption_tx,
+                },
+            );
+            Some(resumption_rx)
+        }
+        _ => None,
+    };
+    // Replay the buffered data to a reconnected receiver if enabled
+    let mut replay_buffer = piping_server
+        .config
+        .resume_buffer_size
+        .map(BytesRingBuffer::new);
+    let mut reattachment_rx = replay_buffer.as_ref().map(|_| {
+        let (reattachment_tx, reattachment_rx) = futures::channel::mpsc::channel(1);
+        piping_server
+            .replayable_transfers
+            .insert(path.clone(), reattachment_tx);
+        reattachment_rx
+    });
 
     tokio::spawn(async move {
-        data_sender_res_body_tx
-            .send(Ok(http_body::Frame::data(Bytes::from(
-                "[INFO] Start sending to 1 receiver(s)...\n",
-            ))))
-            .await
-            .unwrap();
-        // Wait for sender's request body finished
-        if let Ok(_) = sender_req_body_finish_waiter.await {
-            data_sender_res_body_tx
-                .send(Ok(http_body::Frame::data(Bytes::from(
-                    "[INFO] Sent successfully!\n",
-                ))))
-                .await
-                .unwrap();
-        } else {
-            data_sender_res_body_tx
-                .send(Ok(http_body::Frame::data(Bytes::from(
-                    "[INFO] All receiver(s) was/were halfway disconnected.\n",
-                ))))
-                .await
-                .unwrap();
-        }
-        log::info!("Transfer end: '{path}'");
+        data_sender_res_event_tx
+            .send(SenderEvent::Start {
+                n_receivers: n_receivers as u32,
+            })
+            .await;
+        status_hub.publish(
+            &path,
+            StatusEvent::TransferStarted {
+                n_receivers: n_receivers as u32,
+            },
+        );
+        let mut body = counting_body(transfer_request.body, Arc::clone(&n_transferred_bytes));
+        let started_at = std::time::Instant::now();
+        let mut progress_task = progress_interval.map(|progress_interval| {
+            tokio::spawn(send_progress(
+                progress_interval,
+                n_transferred_bytes.clone(),
+                data_sender_res_event_tx.clone(),
+            ))
+        });
+        let status_progress_task = tokio::spawn(publish_progress(
+            piping_server.clone(),
+            path.clone(),
+            n_transferred_bytes.clone(),
+        ));
+        // Set when the resumable upload is interrupted
+        let mut interrupted_at: Option<std::time::Instant> = None;
+        let event: SenderEvent = loop {
+            let next = match (interrupted_at, resumption_rx.as_mut()) {
+                // Wait for a receiver to reconnect
+                _ if receiver_body_senders.is_empty() => {
+                    let Some(reattachment_rx) = reattachment_rx.as_mut() else {
+                        break SenderEvent::AllReceiversDisconnected;
+                    };
+                    match tokio::time::timeout(
+                        piping_server.config.receiver_resumption_grace_period,
+                        reattachment_rx.next(),
+                    )
+                    .await
+                    {
+                        Ok(Some(reattachment)) => NextTransferItem::Reattachment(reattachment),
+                        _ => {
+                            log::info!("No receiver reconnected to '{path}'");
+                            break SenderEvent::AllReceiversDisconnected;
+                        }
+                    }
+                }
+                // Wait for the sender to resume the upload
+                (Some(interrupted_at), Some(resumption_rx)) => {
+                    match tokio::time::timeout_at(
+                        (interrupted_at + piping_server.config.upload_resumption_grace_period)
+                            .into(),
+                        resumption_rx.next(),
+                    )
+                    .await
+                    {
+                        Ok(Some(resumption)) => NextTransferItem::Resumption(resumption),
+                        _ => {
+                            log::info!("The upload on '{path}' was not resumed");
+                            for receiver_body_sender in receiver_body_senders {
+                                receiver_body_sender
+                                    .abort(anyhow!("sender was disconnected halfway"));
+                            }
+                            break SenderEvent::SenderFailed;
+                        }
+                    }
+                }
+                (_, resumption_rx) => {
+                    let Some(next) = with_idle_timeout(
+                        idle_transfer_timeout,
+                        next_transfer_item(&mut body, resumption_rx, reattachment_rx.as_mut()),
+                    )
+                    .await
+                    else {
+                        break stall(&path, idle_transfer_timeout, receiver_body_senders);
+                    };
+                    next
+                }
+            };
+            let frame_result = match next {
+                NextTransferItem::Frame(frame_result) => frame_result,
+                NextTransferItem::Resumption(resumption) => {
+                    let offset = n_transferred_bytes.load(Ordering::Relaxed);
+                    if resumption.offset != offset {
+                        let _ = resumption.reply_tx.send(Err(offset));
+                        continue;
+                    }
+                    if resumption.reply_tx.send(Ok(())).is_err() {
+                        continue;
+                    }
+                    log::info!("The upload on '{path}' was resumed at {offset} byte(s)");
+                    // NOTE: The previous request body is dropped if it is still alive
+                    body = counting_body(
+                        TransferRequestBody::Incoming(resumption.body),
+                        Arc::clone(&n_transferred_bytes),
+                    );
+                    interrupted_at = None;
+                    upload_complete = resumption.complete;
+                    data_sender_res_event_tx = resumption.res_event_tx;
+                    data_sender_res_event_tx
+                        .send(SenderEvent::Resumed { offset })
+                        .await;
+                    status_hub.publish(&path, StatusEvent::Resumed { n_bytes: offset });
+                    if let Some(progress_task) = progress_task.as_mut() {
+                        progress_task.abort();
+                        *progress_task = tokio::spawn(send_progress(
+                            progress_interval.unwrap(),
+                            n_transferred_bytes.clone(),
+                            data_sender_res_event_tx.clone(),
+                        ));
+                    }
+                    continue;
+                }
+                NextTransferItem::Reattachment(reattachment) => {
+                    // NOTE: A disconnected receiver may not have been detected because no data has been sent since
+                    receiver_body_senders.retain(|receiver_body_sender| {
+                        let is_closed = receiver_body_sender.frame_tx.is_closed();
+                        if is_closed {
+                            disconnected_receivers_n_bytes
+                                .push(receiver_body_sender.n_delivered_bytes());
+                        }
+                        !is_closed
+                    });
+                    if receiver_body_senders.len() >= n_receivers {
+                        let _ = reattachment.res_sender.send(receiver_error_response(
+                            http::StatusCode::BAD_REQUEST,
+                            format!("The number of receivers has reached limits on '{path}'."),
+                        ));
+                        continue;
+                    }
+                    let offset = reattachment.offset;
+                    let Some(receiver_body_sender) = reattach_receiver(
+                        &path,
+                        reattachment,
+                        replay_buffer.as_ref().unwrap(),
+                        &receiver_res_headers,
+                        content_length,
+                        &piping_server.config,
+                    )
+                    .await
+                    else {
+                        continue;
+                    };
+                    // The reconnected receiver replaces the disconnected one which received the closest bytes
+                    if let Some(index) = (0..disconnected_receivers_n_bytes.len())
+                        .min_by_key(|&i| disconnected_receivers_n_bytes[i].abs_diff(offset))
+                    {
+                        disconnected_receivers_n_bytes.swap_remove(index);
+                    }
+                    receiver_body_senders.push(receiver_body_sender);
+                    continue;
+                }
+            };
+            match frame_result {
+                Some(Ok(frame)) => {
+                    // Trailers are not transferred
+                    let Ok(data) = frame.into_data() else {
+                        continue;
+                    };
+                    if let Some(replay_buffer) = replay_buffer.as_mut() {
+                        replay_buffer.push(data.clone());
+                    }
+                    // Send the same data to all receivers so that the slowest receiver sets the pace
+                    let send_all = futures::future::join_all(receiver_body_senders.iter_mut().map(
+                        |receiver_body_sender| {
+                            receiver_body_sender
+                                .frame_tx
+                                .send(Ok(http_body::Frame::data(data.clone())))
+                        },
+                    ));
+                    let Some(results) = with_idle_timeout(idle_transfer_timeout, send_all).await
+                    else {
+                        break stall(&path, idle_transfer_timeout, receiver_body_senders);
+                    };
+                    let mut results = results.into_iter();
+                    receiver_body_senders.retain(|receiver_body_sender| {
+                        let is_ok = results.next().unwrap().is_ok();
+                        if !is_ok {
+                            disconnected_receivers_n_bytes
+                                .push(receiver_body_sender.n_delivered_bytes());
+                        }
+                        is_ok
+                    });
+                }
+                Some(Err(err)) => {
+                    log::info!("Failed to receive data from the sender on '{path}': {err:?}");
+                    if resumption_rx.is_some() {
+                        log::info!("Waiting for the upload on '{path}' to be resumed");
+                        status_hub.publish(
+                            &path,
+                            StatusEvent::Interrupted {
+                                n_bytes: n_transferred_bytes.load(Ordering::Relaxed),
+                            },
+                        );
+                        interrupted_at = Some(std::time::Instant::now());
+                        continue;
+                    }
+                    for receiver_body_sender in receiver_body_senders {
+                        // NOTE: Abort rather than end cleanly so that receivers notice truncated data
+                        receiver_body_sender
+                            .abort(anyhow!("sender was disconnected halfway: {err}"));
+                    }
+                    break SenderEvent::SenderFailed;
+                }
+                // If the request body with Upload-Complete: ?0 finished
+                None if !upload_complete && resumption_rx.is_some() => {
+                    let offset = n_transferred_bytes.load(Ordering::Relaxed);
+                    log::info!(
+                        "Waiting for the rest of the upload on '{path}' at {offset} byte(s)"
+                    );
+                    status_hub.publish(&path, StatusEvent::Interrupted { n_bytes: offset });
+                    let _ = tokio::time::timeout(
+                        idle_transfer_timeout.unwrap_or(LAST_SENDER_EVENT_TIMEOUT),
+                        data_sender_res_event_tx.send(SenderEvent::UploadIncomplete { offset }),
+                    )
+                    .await;
+                    data_sender_res_event_tx.close();
+                    interrupted_at = Some(std::time::Instant::now());
+                    continue;
+                }
+                // If sender's request body finished
+                None => {
+                    // Wait until all receivers' response bodies are fully written
+                    for receiver_body_sender in receiver_body_senders.iter_mut() {
+                        receiver_body_sender.frame_tx.close_channel();
+                    }
+                    let finish_all = futures::future::join_all(
+                        receiver_body_senders
+                            .iter_mut()
+                            .map(|receiver_body_sender| &mut receiver_body_sender.finish_waiter),
+                    );
+                    let Some(results) = with_idle_timeout(idle_transfer_timeout, finish_all).await
+                    else {
+                        break stall(&path, idle_transfer_timeout, receiver_body_senders);
+                    };
+                    let receivers: Vec<ReceiverDelivery> = receiver_body_senders
+                        .iter()
+                        .zip(results)
+                        .map(|(receiver_body_sender, result)| ReceiverDelivery {
+                            n_bytes: receiver_body_sender.n_delivered_bytes(),
+                            completed: result.is_ok(),
+                        })
+                        .chain(disconnected_receivers_n_bytes.iter().map(|&n_bytes| {
+                            ReceiverDelivery {
+                                n_bytes,
+                                completed: false,
+                            }
+                        }))
+                        .collect();
+                    if receivers.iter().all(|receiver| !receiver.completed) {
+                        break SenderEvent::AllReceiversDisconnected;
+                    }
+                    if receivers.iter().any(|receiver| !receiver.completed) {
+                        log::info!("Transfer end with some receivers disconnected: '{path}'");
+                        break SenderEvent::PartiallyDelivered {
+                            n_bytes: n_transferred_bytes.load(Ordering::Relaxed),
+                            receivers,
+                        };
+                    }
+                    log::info!("Transfer end: '{path}'");
+                    break SenderEvent::Finished {
+                        n_bytes: receivers
+                            .iter()
+                            .map(|receiver| receiver.n_bytes)
+                            .min()
+                            .unwrap_or_default(),
+                        duration: started_at.elapsed(),
+                    };
+                }
+            }
+        };
+        if let Some(progress_task) = progress_task {
+            progress_task.abort();
+        }
+        status_progress_task.abort();
+        if let Some(upload_token) = upload_token {
+            piping_server.uploads.remove(&upload_token);
+        }
+        if let Some(reattachment_rx) = reattachment_rx {
+            // NOTE: Another transfer may have started on the same path
+            piping_server
+                .replayable_transfers
+                .remove_if(&path, |_, reattachment_tx| {
+                    reattachment_tx.is_connected_to(&reattachment_rx)
+                });
+        }
+        let n_bytes = n_transferred_bytes.load(Ordering::Relaxed);
+        let abort_reason = match &event {
+            SenderEvent::Finished { .. } => None,
+            SenderEvent::Stalled { .. } => Some("stalled"),
+            SenderEvent::SenderFailed => Some("sender_failed"),
+            SenderEvent::PartiallyDelivered { .. } => Some("partially_delivered"),
+            _ => Some("all_receivers_disconnected"),
+        };
+        status_hub.publish(
+            &path,
+            match abort_reason {
+                None => StatusEvent::Finished { n_bytes },
+                Some(reason) => StatusEvent::Aborted { n_bytes, reason },
+            },
+        );
+        piping_server.notify_event_listener(|event_listener| {
+            let transfer_end_event = TransferEndEvent {
+                path: path.clone(),
+                n_bytes,
+                duration: started_at.elapsed(),
+                abort_reason,
+            };
+            match abort_reason {
+                None => event_listener.on_transfer_completed(&transfer_end_event),
+                Some(_) => event_listener.on_transfer_aborted(&transfer_end_event),
+            }
+        });
+        // NOTE: The last event is awaited because scripts parse it, but bounded not to be blocked forever by the sender which does not read the response
+        let _ = tokio::time::timeout(
+            idle_transfer_timeout.unwrap_or(LAST_SENDER_EVENT_TIMEOUT),
+            data_sender_res_event_tx.send(event),
+        )
+        .await;
     });
-    return Ok(());
+    Ok(())
+}
+
+enum NextTransferItem {
+    Frame(Option<Result<http_body::Frame<Bytes>, anyhow::Error>>),
+    Resumption(UploadResumption),
+    Reattachment(ReceiverReattachment),
+}
+
+// Wait for the next frame of the sender's body, a resumption of the upload or a reconnected receiver
+async fn next_transfer_item(
+    body: &mut CountingBody<TransferRequestBody>,
+    resumption_rx: Option<&mut futures::channel::mpsc::Receiver<UploadResumption>>,
+    reattachment_rx: Option<&mut futures::channel::mpsc::Receiver<ReceiverReattachment>>,
+) -> NextTransferItem {
+    tokio::select! {
+        frame_result = body.frame() => NextTransferItem::Frame(frame_result),
+        Some(resumption) = next_or_pending(resumption_rx) => NextTransferItem::Resumption(resumption),
+        Some(reattachment) = next_or_pending(reattachment_rx) => NextTransferItem::Reattachment(reattachment),
+    }
+}
+
+// Wait for the next item forever if the receiver does not exist
+async fn next_or_pending<T>(rx: Option<&mut futures::channel::mpsc::Receiver<T>>) -> Option<T> {
+    match rx {
+        Some(rx) => rx.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Return a partial response to the reconnected receiver and replay the buffered data from its offset
+async fn reattach_receiver(
+    path: &str,
+    reattachment: ReceiverReattachment,
+    replay_buffer: &BytesRingBuffer,
+    receiver_res_headers: &http::HeaderMap,
+    content_length: Option<u64>,
+    config: &PipingServerConfig,
+) -> Option<ReceiverBodySender> {
+    let offset = reattachment.offset;
+    // NOTE: Content-Range cannot tell the range without the length of the data
+    let Some(content_length) = content_length else {
+        log::info!("A receiver on '{path}' requested {offset} byte(s) without Content-Length");
+        let _ = reattachment.res_sender.send(receiver_error_response(
+            http::StatusCode::RANGE_NOT_SATISFIABLE,
+            format!("The transfer without Content-Length cannot be resumed on '{path}'."),
+        ));
+        return None;
+    };
+    if offset >= content_length {
+        log::info!("A receiver on '{path}' requested {offset} byte(s) beyond the data");
+        let mut res = receiver_error_response(
+            http::StatusCode::RANGE_NOT_SATISFIABLE,
+            format!("The offset {offset} is out of {content_length} byte(s) on '{path}'."),
+        );
+        res.headers_mut().insert(
+            http::header::CONTENT_RANGE,
+            format!("bytes */{content_length}").parse().unwrap(),
+        );
+        let _ = reattachment.res_sender.send(res);
+        return None;
+    }
+    let Some(chunks) = replay_buffer.bytes_from(offset) else {
+        log::info!("A receiver on '{path}' requested {offset} byte(s) out of the replay buffer");
+        let _ = reattachment.res_sender.send(receiver_error_response(
+            http::StatusCode::RANGE_NOT_SATISFIABLE,
+            format!("The offset {offset} is no longer available on '{path}'."),
+        ));
+        return None;
+    };
+    let remaining_length = content_length - offset;
+    let (mut receiver_body_sender, receiver_body) =
+        receiver_body_channel(config.channel_buffer_size, Some(remaining_length));
+    let mut receiver_res = http::Response::new(receiver_body);
+    *receiver_res.status_mut() = http::StatusCode::PARTIAL_CONTENT;
+    *receiver_res.headers_mut() = receiver_res_headers.clone();
+    receiver_res.headers_mut().insert(
+        http::header::CONTENT_RANGE,
+        format!("bytes {offset}-{}/{content_length}", content_length - 1)
+            .parse()
+            .unwrap(),
+    );
+    receiver_res
+        .headers_mut()
+        .insert(http::header::CONTENT_LENGTH, remaining_length.into());
+    if reattachment.res_sender.send(receiver_res).is_err() {
+        return None;
+    }
+    log::info!("A receiver reconnected to '{path}' at {offset} byte(s)");
+    // NOTE: Count from the offset so that the count is the position in the data
+    receiver_body_sender
+        .n_delivered_bytes
+        .store(offset, Ordering::Relaxed);
+    let replay = async {
+        for chunk in chunks {
+            receiver_body_sender
+                .frame_tx
+                .send(Ok(http_body::Frame::data(chunk)))
+                .await?;
+        }
+        Ok::<_, futures::channel::mpsc::SendError>(())
+    };
+    match with_idle_timeout(config.idle_transfer_timeout, replay).await {
+        Some(Ok(())) => Some(receiver_body_sender),
+        _ => None,
+    }
+}
+
+// Send the progress of the transfer to the sender periodically
+async fn send_progress(
+    progress_interval: std::time::Duration,
+    n_transferred_bytes: Arc<AtomicU64>,
+    mut res_event_tx: SenderEventSender,
+) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + progress_interval,
+        progress_interval,
+    );
+    let mut last_n_transferred_bytes: u64 = 0;
+    loop {
+        interval.tick().await;
+        let n_bytes = n_transferred_bytes.load(Ordering::Relaxed);
+        let bytes_per_sec =
+            (n_bytes - last_n_transferred_bytes) as f64 / progress_interval.as_secs_f64();
+        last_n_transferred_bytes = n_bytes;
+        // NOTE: try_send() to skip the progress when the sender does not read the response
+        res_event_tx.try_send(SenderEvent::Progress {
+            n_bytes,
+            bytes_per_sec,
+        });
+    }
+}
+
+// Publish the progress of the transfer to status watchers and the event listener periodically
+async fn publish_progress(
+    piping_server: PipingServer,
+    path: String,
+    n_transferred_bytes: Arc<AtomicU64>,
+) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + STATUS_PROGRESS_INTERVAL,
+        STATUS_PROGRESS_INTERVAL,
+    );
+    loop {
+        interval.tick().await;
+        let n_bytes = n_transferred_bytes.load(Ordering::Relaxed);
+        piping_server
+            .status_hub
+            .publish(&path, StatusEvent::Progress { n_bytes });
+        piping_server.notify_event_listener(|event_listener| {
+            event_listener.on_progress(&ProgressEvent {
+                path: path.clone(),
+                n_bytes,
+            })
+        });
+    }
+}
+
+// Run the future with the timeout (no limit if None)
+async fn with_idle_timeout<F: std::future::Future>(
+    idle_timeout: Option<std::time::Duration>,
+    future: F,
+) -> Option<F::Output> {
+    match idle_timeout {
+        Some(idle_timeout) => tokio::time::timeout(idle_timeout, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+// Abort the stalled transfer and return the event for the sender
+fn stall(
+    path: &str,
+    idle_transfer_timeout: Option<std::time::Duration>,
+    receiver_body_senders: Vec<ReceiverBodySender>,
+) -> SenderEvent {
+    let idle_timeout = idle_transfer_timeout.unwrap_or_default();
+    for receiver_body_sender in receiver_body_senders {
+        receiver_body_sender.abort(anyhow!("transfer stalled"));
+    }
+    log::info!(
+        "Transfer aborted because of stall for {} second(s): '{path}'",
+        idle_timeout.as_secs()
+    );
+    SenderEvent::Stalled { idle_timeout }
 }
 
 fn get_n_receivers_result(
     query_params: &HashMap<String, String>,
 ) -> Result<u32, std::num::ParseIntError> {
-    return query_params
+    query_params
         .get("n")
         .map(|s| s.parse())
-        .unwrap_or_else(|| Ok(1));
+        .unwrap_or_else(|| Ok(1))
+}
+
+// Get the wait timeout specified by the "timeout" query parameter such as "30s" and "5m", capped by the maximum
+fn get_wait_timeout_result(
+    query_params: &HashMap<String, String>,
+    max_wait_timeout: Option<std::time::Duration>,
+) -> anyhow::Result<Option<std::time::Duration>> {
+    let Some(timeout) = query_params.get("timeout") else {
+        return Ok(max_wait_timeout);
+    };
+    // NOTE: A number without unit is seconds
+    let timeout = parse_duration(timeout)?;
+    if timeout.is_zero() {
+        anyhow::bail!("timeout should > 0");
+    }
+    Ok(Some(match max_wait_timeout {
+        Some(max_wait_timeout) => timeout.min(max_wait_timeout),
+        None => timeout,
+    }))
+}
+
+// Get the progress interval specified by the "progress" query parameter or X-Piping-Progress header
+fn get_progress_interval_result(
+    query_params: &HashMap<String, String>,
+    headers: &http::HeaderMap,
+) -> anyhow::Result<Option<std::time::Duration>> {
+    let progress_interval = match query_params.get("progress") {
+        Some(progress_interval) => progress_interval.as_str(),
+        None => match headers.get("x-piping-progress") {
+            Some(progress_interval) => progress_interval.to_str()?,
+            None => return Ok(None),
+        },
+    };
+    let progress_interval = parse_duration(progress_interval)?;
+    if progress_interval.is_zero() {
+        anyhow::bail!("progress interval should > 0");
+    }
+    Ok(Some(progress_interval))
+}
+
+// Get the offset to resume an upload specified by Upload-Offset or Content-Range header
+fn get_upload_offset_result(headers: &http::HeaderMap) -> anyhow::Result<Option<u64>> {
+    if let Some(upload_offset) = headers.get("upload-offset") {
+        return Ok(Some(upload_offset.to_str()?.parse()?));
+    }
+    let Some(content_range) = headers.get("content-range") else {
+        return Ok(None);
+    };
+    // e.g. "bytes 100-*/*", "bytes 100-199/200"
+    let (start, _) = content_range
+        .to_str()?
+        .strip_prefix("bytes ")
+        .and_then(|range| range.split_once('-'))
+        .ok_or_else(|| anyhow!("invalid Content-Range"))?;
+    Ok(Some(start.trim().parse()?))
+}
+
+// Replace values of query parameters which are credentials not to leak them in logs
+fn redact_credential_query_params(path_and_query: &str) -> std::borrow::Cow<'_, str> {
+    const CREDENTIAL_QUERY_PARAMS: [&str; 3] = ["token", "sig", "upload"];
+    let Some((path, query)) = path_and_query.split_once('?') else {
+        return path_and_query.into();
+    };
+    let query: Vec<std::borrow::Cow<str>> = query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if CREDENTIAL_QUERY_PARAMS.contains(&name) => {
+                format!("{name}=REDACTED").into()
+            }
+            _ => param.into(),
+        })
+        .collect();
+    format!("{path}?{}", query.join("&")).into()
+}
+
+// Get the value of Upload-Complete header, which is a boolean of Structured Field Values
+fn get_upload_complete_result(headers: &http::HeaderMap) -> anyhow::Result<Option<bool>> {
+    let Some(upload_complete) = headers.get("upload-complete") else {
+        return Ok(None);
+    };
+    match upload_complete.as_bytes() {
+        b"?0" => Ok(Some(false)),
+        b"?1" => Ok(Some(true)),
+        _ => Err(anyhow!("invalid Upload-Complete")),
+    }
+}
+
+// Get the start of the range specified by Range header (e.g. "bytes=100-")
+// NOTE: Other forms of ranges are ignored
+fn get_range_start(headers: &http::HeaderMap) -> Option<u64> {
+    let (start, end) = headers
+        .get("range")?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes=")?
+        .split_once('-')?;
+    if !end.trim().is_empty() {
+        return None;
+    }
+    start.trim().parse().ok()
+}
+
+// Rejection response for a sender in the requested format
+fn sender_rejection_response<B>(
+    format: SenderEventFormat,
+    message: String,
+    to_body: impl FnOnce(Bytes) -> B,
+) -> http::Response<B> {
+    let body = to_body(
+        SenderEvent::Rejected {
+            message: message.clone(),
+        }
+        .to_bytes(format),
+    );
+    http::Response::builder()
+        .status(400)
+        .header("Content-Type", format.content_type())
+        .extension(Rejection { reason: message })
+        .body(body)
+        .unwrap()
+}
+
+// Set Access-Control-Allow-Origin header to every response or remove CORS headers according to the config
+fn set_cors_headers(config: &PipingServerConfig, headers: &mut http::HeaderMap) {
+    match &config.cors_allow_origin {
+        Some(cors_allow_origin) => {
+            headers.insert(
+                http::header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                cors_allow_origin.clone(),
+            );
+            // NOTE: Caches should not reuse the response for other origins
+            if cors_allow_origin != "*" {
+                headers.append(http::header::VARY, http::HeaderValue::from_static("Origin"));
+            }
+        }
+        None => {
+            for name in [
+                "access-control-allow-origin",
+                "access-control-allow-methods",
+                "access-control-allow-headers",
+                "access-control-allow-private-network",
+                "access-control-expose-headers",
+                "access-control-max-age",
+            ] {
+                headers.remove(name);
+            }
+        }
+    }
 }
 
-fn rejection_response<B>(body: B) -> http::Response<B> {
+fn rejection_response<B>(message: String, to_body: impl FnOnce(Bytes) -> B) -> http::Response<B> {
+    let body = to_body(Bytes::from(format!("[ERROR] {message}\n")));
     http::Response::builder()
         .status(400)
         .header("Content-Type", "text/plain")
-        .header("Access-Control-Allow-Origin", "*")
+        .extension(Rejection { reason: message })
         .body(body)
         .unwrap()
 }
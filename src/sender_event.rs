@@ -0,0 +1,324 @@
+use hyper::body::Bytes;
+use std::collections::HashMap;
+
+use crate::util::format_bytes;
+
+// Event notified to the sender in its response
+pub enum SenderEvent {
+    Waiting {
+        n_receivers: u32,
+    },
+    ReceiverConnected {
+        n_connected_receivers: u32,
+    },
+    ReceiversConnected {
+        n_receivers: u32,
+    },
+    Start {
+        n_receivers: u32,
+    },
+    Progress {
+        n_bytes: u64,
+        bytes_per_sec: f64,
+    },
+    Finished {
+        n_bytes: u64,
+        duration: std::time::Duration,
+    },
+    Resumed {
+        offset: u64,
+    },
+    // The request body with Upload-Complete: ?0 ended and the upload waits for the rest
+    UploadIncomplete {
+        offset: u64,
+    },
+    Stored {
+        n_bytes: u64,
+        ttl: std::time::Duration,
+    },
+    // Some receivers were disconnected before receiving all the data
+    PartiallyDelivered {
+        n_bytes: u64,
+        receivers: Vec<ReceiverDelivery>,
+    },
+    AllReceiversDisconnected,
+    SenderFailed,
+    Stalled {
+        idle_timeout: std::time::Duration,
+    },
+    WaitTimedOut {
+        wait_timeout: std::time::Duration,
+    },
+    Rejected {
+        message: String,
+    },
+}
+
+// Bytes delivered to a receiver and whether the receiver received all the data
+#[derive(Clone, Copy, Debug)]
+pub struct ReceiverDelivery {
+    pub n_bytes: u64,
+    pub completed: bool,
+}
+
+impl SenderEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            SenderEvent::Waiting { .. } => "waiting",
+            SenderEvent::ReceiverConnected { .. } => "receiver_connected",
+            SenderEvent::ReceiversConnected { .. } => "receivers_connected",
+            SenderEvent::Start { .. } => "start",
+            SenderEvent::Progress { .. } => "progress",
+            SenderEvent::Finished { .. } => "finished",
+            SenderEvent::Resumed { .. } => "resumed",
+            SenderEvent::UploadIncomplete { .. } => "upload_incomplete",
+            SenderEvent::Stored { .. } => "stored",
+            SenderEvent::PartiallyDelivered { .. } => "partially_delivered",
+            SenderEvent::AllReceiversDisconnected => "all_receivers_disconnected",
+            SenderEvent::SenderFailed => "sender_failed",
+            SenderEvent::Stalled { .. } => "stalled",
+            SenderEvent::WaitTimedOut { .. } => "wait_timed_out",
+            SenderEvent::Rejected { .. } => "rejected",
+        }
+    }
+
+    fn is_error(&self) -> bool {
+        matches!(
+            self,
+            SenderEvent::SenderFailed
+                | SenderEvent::PartiallyDelivered { .. }
+                | SenderEvent::Stalled { .. }
+                | SenderEvent::WaitTimedOut { .. }
+                | SenderEvent::Rejected { .. }
+        )
+    }
+
+    fn message(&self) -> String {
+        match self {
+            SenderEvent::Waiting { n_receivers } => {
+                format!("Waiting for {n_receivers} receiver(s)...")
+            }
+            SenderEvent::ReceiverConnected { .. } => "A receiver was connected.".to_owned(),
+            SenderEvent::ReceiversConnected { n_receivers } => {
+                format!("{n_receivers} receiver(s) has/have been connected.")
+            }
+            SenderEvent::Start { n_receivers } => {
+                format!("Start sending to {n_receivers} receiver(s)...")
+            }
+            SenderEvent::Progress {
+                n_bytes,
+                bytes_per_sec,
+            } => format!(
+                "{} transferred ({}/s)",
+                format_bytes(*n_bytes as f64),
+                format_bytes(*bytes_per_sec),
+            ),
+            SenderEvent::Finished { n_bytes, duration } => format!(
+                "Sent successfully! {n_bytes} bytes delivered in {:.3}s ({}/s).",
+                duration.as_secs_f64(),
+                format_bytes(*n_bytes as f64 / duration.as_secs_f64()),
+            ),
+            SenderEvent::Resumed { offset } => {
+                format!("Resumed the upload at {offset} byte(s).")
+            }
+            SenderEvent::UploadIncomplete { offset } => {
+                format!("Uploaded {offset} byte(s). Append the rest to complete the upload.")
+            }
+            SenderEvent::Stored { n_bytes, ttl } => format!(
+                "Stored {n_bytes} bytes for {} second(s) until a receiver connects.",
+                ttl.as_secs()
+            ),
+            SenderEvent::PartiallyDelivered { receivers, .. } => format!(
+                "Delivered to {} of {} receiver(s): {}.",
+                receivers
+                    .iter()
+                    .filter(|receiver| receiver.completed)
+                    .count(),
+                receivers.len(),
+                receivers
+                    .iter()
+                    .map(|receiver| if receiver.completed {
+                        format!("{} bytes", receiver.n_bytes)
+                    } else {
+                        format!("{} bytes (disconnected)", receiver.n_bytes)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            SenderEvent::AllReceiversDisconnected => {
+                "All receiver(s) was/were halfway disconnected.".to_owned()
+            }
+            SenderEvent::SenderFailed => "Failed to receive data.".to_owned(),
+            SenderEvent::Stalled { idle_timeout } => format!(
+                "transfer stalled: no data moved for {} second(s).",
+                idle_timeout.as_secs()
+            ),
+            SenderEvent::WaitTimedOut { wait_timeout } => format!(
+                "No receiver connected within {} second(s).",
+                wait_timeout.as_secs_f64()
+            ),
+            SenderEvent::Rejected { message } => message.clone(),
+        }
+    }
+
+    // Fields specific to the event in JSON format
+    fn json_fields(&self) -> serde_json::Map<String, serde_json::Value> {
+        let value = match self {
+            SenderEvent::Waiting { n_receivers }
+            | SenderEvent::ReceiversConnected { n_receivers }
+            | SenderEvent::Start { n_receivers } => {
+                serde_json::json!({ "n_receivers": n_receivers })
+            }
+            SenderEvent::ReceiverConnected {
+                n_connected_receivers,
+            } => serde_json::json!({ "n_connected_receivers": n_connected_receivers }),
+            SenderEvent::Progress {
+                n_bytes,
+                bytes_per_sec,
+            } => serde_json::json!({ "n_bytes": n_bytes, "bytes_per_sec": bytes_per_sec }),
+            SenderEvent::Finished { n_bytes, duration } => serde_json::json!({
+                "n_bytes": n_bytes,
+                "duration_secs": duration.as_secs_f64(),
+                "bytes_per_sec": *n_bytes as f64 / duration.as_secs_f64(),
+            }),
+            SenderEvent::PartiallyDelivered { n_bytes, receivers } => serde_json::json!({
+                "n_bytes": n_bytes,
+                "receivers": receivers
+                    .iter()
+                    .map(|receiver| serde_json::json!({
+                        "n_bytes": receiver.n_bytes,
+                        "completed": receiver.completed,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+            SenderEvent::Resumed { offset } | SenderEvent::UploadIncomplete { offset } => {
+                serde_json::json!({ "offset": offset })
+            }
+            SenderEvent::Stored { n_bytes, ttl } => {
+                serde_json::json!({ "n_bytes": n_bytes, "ttl_secs": ttl.as_secs() })
+            }
+            SenderEvent::Stalled { idle_timeout } => {
+                serde_json::json!({ "idle_timeout_secs": idle_timeout.as_secs() })
+            }
+            SenderEvent::WaitTimedOut { wait_timeout } => {
+                serde_json::json!({ "wait_timeout_secs": wait_timeout.as_secs_f64() })
+            }
+            SenderEvent::AllReceiversDisconnected
+            | SenderEvent::SenderFailed
+            | SenderEvent::Rejected { .. } => serde_json::json!({}),
+        };
+        match value {
+            serde_json::Value::Object(fields) => fields,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn to_bytes(&self, format: SenderEventFormat) -> Bytes {
+        match format {
+            SenderEventFormat::Text => {
+                let level = if self.is_error() { "ERROR" } else { "INFO" };
+                Bytes::from(format!("[{level}] {}\n", self.message()))
+            }
+            SenderEventFormat::Json => {
+                let timestamp_ms = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                let mut json = serde_json::json!({
+                    "event": self.name(),
+                    "level": if self.is_error() { "error" } else { "info" },
+                    "message": self.message(),
+                    "timestamp_ms": timestamp_ms,
+                });
+                json.as_object_mut().unwrap().extend(self.json_fields());
+                let mut line = json.to_string();
+                line.push('\n');
+                Bytes::from(line)
+            }
+        }
+    }
+}
+
+// Format of the sender's response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SenderEventFormat {
+    Text,
+    // Newline-delimited JSON
+    Json,
+}
+
+impl SenderEventFormat {
+    // Select the format by "format" query parameter or Accept header
+    pub fn from_request(
+        query_params: &HashMap<String, String>,
+        headers: &http::HeaderMap,
+    ) -> SenderEventFormat {
+        if let Some(format) = query_params.get("format") {
+            return if format == "json" {
+                SenderEventFormat::Json
+            } else {
+                SenderEventFormat::Text
+            };
+        }
+        let accepts_ndjson = headers
+            .get_all("accept")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media_range| {
+                media_range.split(';').next().unwrap_or_default().trim() == "application/x-ndjson"
+            });
+        if accepts_ndjson {
+            SenderEventFormat::Json
+        } else {
+            SenderEventFormat::Text
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SenderEventFormat::Text => "text/plain",
+            SenderEventFormat::Json => "application/x-ndjson",
+        }
+    }
+}
+
+// Sending side of the sender's response body
+#[derive(Clone)]
+pub struct SenderEventSender {
+    tx: futures::channel::mpsc::Sender<Result<http_body::Frame<Bytes>, anyhow::Error>>,
+    format: SenderEventFormat,
+}
+
+impl SenderEventSender {
+    pub fn new(
+        tx: futures::channel::mpsc::Sender<Result<http_body::Frame<Bytes>, anyhow::Error>>,
+        format: SenderEventFormat,
+    ) -> Self {
+        SenderEventSender { tx, format }
+    }
+
+    pub async fn send(&mut self, event: SenderEvent) {
+        use futures::SinkExt as _;
+        let _ = self
+            .tx
+            .send(Ok(http_body::Frame::data(event.to_bytes(self.format))))
+            .await;
+    }
+
+    // Send without waiting (the event is dropped if the sender does not read the response)
+    pub fn try_send(&mut self, event: SenderEvent) {
+        let _ = self
+            .tx
+            .try_send(Ok(http_body::Frame::data(event.to_bytes(self.format))));
+    }
+
+    // End the response after the sent events
+    pub fn close(&mut self) {
+        self.tx.close_channel();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
@@ -0,0 +1,48 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use http_body_util::BodyExt as _;
+use hyper::body::Bytes;
+
+use crate::piping_server::{PipingServer, ResponseBody};
+
+// Mountable in tower stacks such as axum
+// NOTE: Whether HTTPS is used is specified by UsesHttps in the request extensions
+impl<B> tower_service::Service<http::Request<B>> for PipingServer
+where
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Response = http::Response<ResponseBody>;
+    // Errors are responded as 500 so that the service can be nested in a router requiring an infallible service
+    type Error = core::convert::Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let piping_server = self.clone();
+        Box::pin(async move {
+            match piping_server.handle_request(req).await {
+                Ok(res) => Ok(res),
+                Err(err) => {
+                    log::error!("Failed to handle a request: {err:?}");
+                    Ok(http::Response::builder()
+                        .status(500)
+                        .header("Content-Type", "text/plain")
+                        .body(
+                            http_body_util::Full::new(Bytes::from(
+                                "[ERROR] Internal server error.\n",
+                            ))
+                            .map_err(|never| match never {})
+                            .boxed_unsync(),
+                        )
+                        .unwrap())
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,529 @@
+use http_body_util::BodyExt as _;
+use hyper::body::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+// Interval to remove expired data from the spool
+const SPOOL_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+// File name of the index of stored data in the spool directory
+const SPOOL_INDEX_FILE_NAME: &str = "index.json";
+const SPOOL_DATA_FILE_EXTENSION: &str = "data";
+// Size of a chunk read from a spool file
+const SPOOL_READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct SpoolLimits {
+    // Maximum size of data stored by a sender (no limit if None)
+    pub max_file_size: Option<u64>,
+    // Maximum total size of data in the spool (no limit if None)
+    pub max_total_size: Option<u64>,
+    // Maximum duration for which data is stored (no limit if None)
+    pub max_ttl: Option<std::time::Duration>,
+}
+
+// Data stored until a receiver takes it
+#[derive(Clone, Debug)]
+pub struct SpoolEntry {
+    id: String,
+    pub size: u64,
+    expires_at: std::time::SystemTime,
+    pub content_type: Option<String>,
+    pub content_disposition: Option<String>,
+    pub x_pipings: Vec<String>,
+}
+
+impl SpoolEntry {
+    fn is_expired(&self) -> bool {
+        self.expires_at <= std::time::SystemTime::now()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "size": self.size,
+            "expires_at": self
+                .expires_at
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            "content_type": self.content_type,
+            "content_disposition": self.content_disposition,
+            "x_pipings": self.x_pipings,
+        })
+    }
+
+    fn from_json(json: &serde_json::Value) -> Option<SpoolEntry> {
+        let optional_string = |key: &str| json.get(key)?.as_str().map(|s| s.to_owned());
+        let id = json.get("id")?.as_str()?.to_owned();
+        // Only a file name generated by the spool is accepted
+        if !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return None;
+        }
+        Some(SpoolEntry {
+            id,
+            size: json.get("size")?.as_u64()?,
+            expires_at: std::time::UNIX_EPOCH
+                + std::time::Duration::from_secs(json.get("expires_at")?.as_u64()?),
+            content_type: optional_string("content_type"),
+            content_disposition: optional_string("content_disposition"),
+            x_pipings: json
+                .get("x_pipings")?
+                .as_array()?
+                .iter()
+                .filter_map(|value| value.as_str().map(|s| s.to_owned()))
+                .collect(),
+        })
+    }
+}
+
+// Metadata of data to be stored
+pub struct SpoolMetadata {
+    pub content_type: Option<String>,
+    pub content_disposition: Option<String>,
+    pub x_pipings: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum SpoolStoreError {
+    AlreadyStored,
+    TooLarge { max_file_size: u64 },
+    Full,
+    Failed(anyhow::Error),
+}
+
+struct SpoolState {
+    path_to_entry: HashMap<String, SpoolEntry>,
+    // Paths where senders are storing data
+    storing_paths: HashSet<String>,
+    // Total size of stored data and data being stored
+    total_size: u64,
+    // Incremented whenever the index is updated
+    index_generation: u64,
+}
+
+struct SpoolInner {
+    dir: PathBuf,
+    limits: SpoolLimits,
+    state: Mutex<SpoolState>,
+    // Generation of the index written to the file last, locked while writing
+    written_index_generation: Mutex<u64>,
+}
+
+// On-disk store of data sent with "store" query parameter
+#[derive(Clone)]
+pub struct Spool {
+    inner: Arc<SpoolInner>,
+}
+
+impl Spool {
+    // Open the spool directory, restoring the stored data from the index
+    pub fn open(dir: PathBuf, limits: SpoolLimits) -> anyhow::Result<Spool> {
+        std::fs::create_dir_all(&dir)?;
+        let index_path = dir.join(SPOOL_INDEX_FILE_NAME);
+        let mut path_to_entry: HashMap<String, SpoolEntry> = HashMap::new();
+        match std::fs::read(&index_path) {
+            Ok(index_bytes) => {
+                let index: serde_json::Value = serde_json::from_slice(&index_bytes)?;
+                if let Some(entries) = index.get("entries").and_then(|e| e.as_object()) {
+                    for (path, entry) in entries {
+                        let Some(entry) = SpoolEntry::from_json(entry) else {
+                            log::warn!("Invalid spool entry of '{path}' is ignored");
+                            continue;
+                        };
+                        path_to_entry.insert(path.clone(), entry);
+                    }
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let spool = Spool {
+            inner: Arc::new(SpoolInner {
+                dir,
+                limits,
+                state: Mutex::new(SpoolState {
+                    path_to_entry: HashMap::new(),
+                    storing_paths: HashSet::new(),
+                    total_size: 0,
+                    index_generation: 0,
+                }),
+                written_index_generation: Mutex::new(0),
+            }),
+        };
+        // Keep only unexpired entries whose files exist
+        path_to_entry.retain(|_, entry| {
+            !entry.is_expired()
+                && std::fs::metadata(spool.data_file_path(&entry.id))
+                    .map_or(false, |metadata| metadata.len() == entry.size)
+        });
+        let ids: HashSet<&str> = path_to_entry
+            .values()
+            .map(|entry| entry.id.as_str())
+            .collect();
+        // Remove files of expired entries and incomplete data
+        for dir_entry in std::fs::read_dir(&spool.inner.dir)? {
+            let file_path = dir_entry?.path();
+            let is_data_file = file_path
+                .extension()
+                .map_or(false, |extension| extension == SPOOL_DATA_FILE_EXTENSION);
+            let is_indexed = file_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map_or(false, |stem| ids.contains(stem));
+            if is_data_file && !is_indexed {
+                std::fs::remove_file(&file_path)?;
+            }
+        }
+        {
+            let mut state = spool.inner.state.lock().unwrap();
+            state.total_size = path_to_entry.values().map(|entry| entry.size).sum();
+            state.path_to_entry = path_to_entry;
+            write_index(&spool.inner.dir, &index_json(&state))?;
+            log::info!(
+                "Spool opened with {} stored data: {:?}",
+                state.path_to_entry.len(),
+                spool.inner.dir
+            );
+        }
+        Ok(spool)
+    }
+
+    // Remove expired data periodically while the spool is alive
+    pub fn spawn_sweeper(&self) {
+        let spool: Weak<SpoolInner> = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(SPOOL_SWEEP_INTERVAL).await;
+                let Some(inner) = spool.upgrade() else {
+                    return;
+                };
+                Spool { inner }.sweep();
+            }
+        });
+    }
+
+    pub fn sweep(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        let expired_paths: Vec<String> = state
+            .path_to_entry
+            .iter()
+            .filter(|(_, entry)| entry.is_expired())
+            .map(|(path, _)| path.clone())
+            .collect();
+        if expired_paths.is_empty() {
+            return;
+        }
+        for path in expired_paths {
+            let entry = state.path_to_entry.remove(&path).unwrap();
+            self.remove_data_file(&mut state, &entry);
+            log::info!("Stored data on '{path}' expired");
+        }
+        self.persist_index(&mut state);
+    }
+
+    pub fn max_ttl(&self) -> Option<std::time::Duration> {
+        self.inner.limits.max_ttl
+    }
+
+    // Store the body until a receiver takes it and return the stored size
+    pub async fn store<B>(
+        &self,
+        path: &str,
+        ttl: std::time::Duration,
+        metadata: SpoolMetadata,
+        content_length: Option<u64>,
+        body: B,
+    ) -> Result<u64, SpoolStoreError>
+    where
+        B: http_body::Body<Data = Bytes, Error = anyhow::Error> + Unpin,
+    {
+        if let (Some(content_length), Some(max_file_size)) =
+            (content_length, self.inner.limits.max_file_size)
+        {
+            if content_length > max_file_size {
+                return Err(SpoolStoreError::TooLarge { max_file_size });
+            }
+        }
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            let is_stored = state
+                .path_to_entry
+                .get(path)
+                .map_or(false, |entry| !entry.is_expired());
+            if is_stored || !state.storing_paths.insert(path.to_owned()) {
+                return Err(SpoolStoreError::AlreadyStored);
+            }
+        }
+        let id = {
+            use base64::Engine as _;
+            let mut id_bytes = [0u8; 16];
+            getrandom::getrandom(&mut id_bytes).unwrap();
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(id_bytes)
+        };
+        // NOTE: The guard cleans up also when this future is dropped halfway
+        let mut storing_guard = StoringGuard {
+            spool: self,
+            path: path.to_owned(),
+            id: id.clone(),
+            size: 0,
+            committed: false,
+        };
+        self.write_data_file(&id, body, &mut storing_guard.size)
+            .await?;
+        let size = storing_guard.size;
+        let write_index = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.storing_paths.remove(path);
+            storing_guard.committed = true;
+            // Replace the expired data if exists
+            if let Some(expired_entry) = state.path_to_entry.remove(path) {
+                self.remove_data_file(&mut state, &expired_entry);
+            }
+            state.path_to_entry.insert(
+                path.to_owned(),
+                SpoolEntry {
+                    id,
+                    size,
+                    expires_at: std::time::SystemTime::now() + ttl,
+                    content_type: metadata.content_type,
+                    content_disposition: metadata.content_disposition,
+                    x_pipings: metadata.x_pipings,
+                },
+            );
+            self.index_writer(&mut state)
+        };
+        // NOTE: The index is written before the sender is told that the data is stored
+        let _ = tokio::task::spawn_blocking(write_index).await;
+        Ok(size)
+    }
+
+    // Write the body to a new data file, counting the written size in the total size
+    async fn write_data_file<B>(
+        &self,
+        id: &str,
+        mut body: B,
+        size: &mut u64,
+    ) -> Result<(), SpoolStoreError>
+    where
+        B: http_body::Body<Data = Bytes, Error = anyhow::Error> + Unpin,
+    {
+        let mut file = tokio::fs::File::create(self.data_file_path(id))
+            .await
+            .map_err(|err| SpoolStoreError::Failed(err.into()))?;
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(SpoolStoreError::Failed)?;
+            // Trailers are not stored
+            let Ok(data) = frame.into_data() else {
+                continue;
+            };
+            let n_bytes = data.len() as u64;
+            if let Some(max_file_size) = self.inner.limits.max_file_size {
+                if *size + n_bytes > max_file_size {
+                    return Err(SpoolStoreError::TooLarge { max_file_size });
+                }
+            }
+            {
+                let mut state = self.inner.state.lock().unwrap();
+                if let Some(max_total_size) = self.inner.limits.max_total_size {
+                    if state.total_size + n_bytes > max_total_size {
+                        return Err(SpoolStoreError::Full);
+                    }
+                }
+                state.total_size += n_bytes;
+                *size += n_bytes;
+            }
+            file.write_all(&data)
+                .await
+                .map_err(|err| SpoolStoreError::Failed(err.into()))?;
+        }
+        file.sync_all()
+            .await
+            .map_err(|err| SpoolStoreError::Failed(err.into()))?;
+        Ok(())
+    }
+
+    // Take the unexpired data stored on the path
+    pub fn take(&self, path: &str) -> Option<SpoolEntry> {
+        let mut state = self.inner.state.lock().unwrap();
+        let entry = state.path_to_entry.remove(path)?;
+        self.persist_index(&mut state);
+        if entry.is_expired() {
+            self.remove_data_file(&mut state, &entry);
+            return None;
+        }
+        Some(entry)
+    }
+
+    // Stream of the taken data, which is removed after fully read
+    // NOTE: The data is put back to the spool if the stream is dropped halfway
+    pub async fn read(
+        &self,
+        path: &str,
+        entry: SpoolEntry,
+    ) -> anyhow::Result<
+        impl futures::Stream<Item = Result<http_body::Frame<Bytes>, anyhow::Error>> + Send,
+    > {
+        let file = match tokio::fs::File::open(self.data_file_path(&entry.id)).await {
+            Ok(file) => file,
+            Err(err) => {
+                let mut state = self.inner.state.lock().unwrap();
+                state.total_size -= entry.size;
+                return Err(err.into());
+            }
+        };
+        let reader = SpoolReader {
+            spool: self.clone(),
+            path: path.to_owned(),
+            entry,
+            file,
+            n_read_bytes: 0,
+        };
+        Ok(futures::stream::unfold(reader, |mut reader| async move {
+            if reader.n_read_bytes >= reader.entry.size {
+                return None;
+            }
+            let mut buf = vec![0u8; SPOOL_READ_CHUNK_SIZE];
+            match reader.file.read(&mut buf).await {
+                Ok(0) => Some((
+                    Err(anyhow::anyhow!("spool file was truncated")),
+                    reader.into_finished(),
+                )),
+                Ok(n) => {
+                    buf.truncate(n);
+                    reader.n_read_bytes += n as u64;
+                    Some((Ok(http_body::Frame::data(Bytes::from(buf))), reader))
+                }
+                Err(err) => Some((Err(err.into()), reader.into_finished())),
+            }
+        }))
+    }
+
+    fn data_file_path(&self, id: &str) -> PathBuf {
+        self.inner
+            .dir
+            .join(format!("{id}.{SPOOL_DATA_FILE_EXTENSION}"))
+    }
+
+    fn remove_data_file(&self, state: &mut SpoolState, entry: &SpoolEntry) {
+        state.total_size -= entry.size;
+        if let Err(err) = std::fs::remove_file(self.data_file_path(&entry.id)) {
+            log::error!("Failed to remove a spool file: {err:?}");
+        }
+    }
+
+    // Write the index in the background to restore stored data after restart
+    fn persist_index(&self, state: &mut SpoolState) {
+        let write = self.index_writer(state);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(write);
+            }
+            Err(_) => write(),
+        }
+    }
+
+    // Blocking function to write the current index, which is run without the state locked
+    fn index_writer(&self, state: &mut SpoolState) -> impl FnOnce() + Send + 'static {
+        state.index_generation += 1;
+        let generation = state.index_generation;
+        let index = index_json(state);
+        let inner = Arc::clone(&self.inner);
+        move || {
+            let mut written_index_generation = inner.written_index_generation.lock().unwrap();
+            // NOTE: Writes can run out of order, so an older index is not written over a newer one
+            if *written_index_generation >= generation {
+                return;
+            }
+            match write_index(&inner.dir, &index) {
+                Ok(()) => *written_index_generation = generation,
+                Err(err) => log::error!("Failed to write the spool index: {err:?}"),
+            }
+        }
+    }
+}
+
+fn index_json(state: &SpoolState) -> String {
+    let entries: serde_json::Map<String, serde_json::Value> = state
+        .path_to_entry
+        .iter()
+        .map(|(path, entry)| (path.clone(), entry.to_json()))
+        .collect();
+    serde_json::json!({ "version": 1, "entries": entries }).to_string()
+}
+
+// Write the index atomically
+fn write_index(dir: &std::path::Path, index: &str) -> std::io::Result<()> {
+    let index_path = dir.join(SPOOL_INDEX_FILE_NAME);
+    let tmp_index_path = dir.join(format!("{SPOOL_INDEX_FILE_NAME}.tmp"));
+    std::fs::write(&tmp_index_path, index)?;
+    std::fs::rename(tmp_index_path, index_path)
+}
+
+// Reservation of a path being stored, which is undone with the partial data unless committed
+struct StoringGuard<'a> {
+    spool: &'a Spool,
+    path: String,
+    id: String,
+    // Size counted in the total size
+    size: u64,
+    committed: bool,
+}
+
+impl Drop for StoringGuard<'_> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        {
+            let mut state = self.spool.inner.state.lock().unwrap();
+            state.storing_paths.remove(&self.path);
+            state.total_size -= self.size;
+        }
+        match std::fs::remove_file(self.spool.data_file_path(&self.id)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                log::error!("Failed to remove a partial spool file: {err:?}");
+            }
+            _ => {}
+        }
+    }
+}
+
+struct SpoolReader {
+    spool: Spool,
+    path: String,
+    entry: SpoolEntry,
+    file: tokio::fs::File,
+    n_read_bytes: u64,
+}
+
+impl SpoolReader {
+    // Make the stream end after an error
+    fn into_finished(mut self) -> SpoolReader {
+        self.n_read_bytes = u64::MAX;
+        self
+    }
+}
+
+impl Drop for SpoolReader {
+    fn drop(&mut self) {
+        let mut state = self.spool.inner.state.lock().unwrap();
+        let is_read_halfway = self.n_read_bytes < self.entry.size;
+        if is_read_halfway
+            && !self.entry.is_expired()
+            && !state.path_to_entry.contains_key(&self.path)
+        {
+            log::info!("Stored data on '{}' is put back to the spool", self.path);
+            state
+                .path_to_entry
+                .insert(self.path.clone(), self.entry.clone());
+        } else {
+            self.spool.remove_data_file(&mut state, &self.entry);
+        }
+        self.spool.persist_index(&mut state);
+    }
+}
@@ -0,0 +1,159 @@
+use futures::StreamExt as _;
+use hyper::body::Bytes;
+use std::sync::Arc;
+
+// Capacity of events buffered for a slow status watcher
+const STATUS_CHANNEL_CAPACITY: usize = 64;
+
+// Event in the lifecycle of a pipe notified to status watchers
+#[derive(Clone, Debug)]
+pub enum StatusEvent {
+    // Current state when a watcher is connected
+    State {
+        sender_waiting: bool,
+        n_waiting_receivers: u32,
+    },
+    SenderWaiting {
+        n_receivers: u32,
+    },
+    ReceiverWaiting {
+        n_receivers: u32,
+        n_waiting_receivers: u32,
+    },
+    SenderLeft,
+    ReceiverLeft,
+    TransferStarted {
+        n_receivers: u32,
+    },
+    Progress {
+        n_bytes: u64,
+    },
+    // The sender of the resumable upload was disconnected
+    Interrupted {
+        n_bytes: u64,
+    },
+    Resumed {
+        n_bytes: u64,
+    },
+    Finished {
+        n_bytes: u64,
+    },
+    Aborted {
+        n_bytes: u64,
+        reason: &'static str,
+    },
+}
+
+impl StatusEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            StatusEvent::State { .. } => "state",
+            StatusEvent::SenderWaiting { .. } => "sender_waiting",
+            StatusEvent::ReceiverWaiting { .. } => "receiver_waiting",
+            StatusEvent::SenderLeft => "sender_left",
+            StatusEvent::ReceiverLeft => "receiver_left",
+            StatusEvent::TransferStarted { .. } => "transfer_started",
+            StatusEvent::Progress { .. } => "progress",
+            StatusEvent::Interrupted { .. } => "interrupted",
+            StatusEvent::Resumed { .. } => "resumed",
+            StatusEvent::Finished { .. } => "finished",
+            StatusEvent::Aborted { .. } => "aborted",
+        }
+    }
+
+    fn data(&self) -> serde_json::Value {
+        match self {
+            StatusEvent::State {
+                sender_waiting,
+                n_waiting_receivers,
+            } => serde_json::json!({
+                "sender_waiting": sender_waiting,
+                "n_waiting_receivers": n_waiting_receivers,
+            }),
+            StatusEvent::SenderWaiting { n_receivers }
+            | StatusEvent::TransferStarted { n_receivers } => {
+                serde_json::json!({ "n_receivers": n_receivers })
+            }
+            StatusEvent::ReceiverWaiting {
+                n_receivers,
+                n_waiting_receivers,
+            } => serde_json::json!({
+                "n_receivers": n_receivers,
+                "n_waiting_receivers": n_waiting_receivers,
+            }),
+            StatusEvent::SenderLeft | StatusEvent::ReceiverLeft => serde_json::json!({}),
+            StatusEvent::Progress { n_bytes }
+            | StatusEvent::Interrupted { n_bytes }
+            | StatusEvent::Resumed { n_bytes }
+            | StatusEvent::Finished { n_bytes } => {
+                serde_json::json!({ "n_bytes": n_bytes })
+            }
+            StatusEvent::Aborted { n_bytes, reason } => {
+                serde_json::json!({ "n_bytes": n_bytes, "reason": reason })
+            }
+        }
+    }
+
+    // Server-Sent Events representation
+    pub fn to_sse_bytes(&self) -> Bytes {
+        Bytes::from(format!("event: {}\ndata: {}\n\n", self.name(), self.data()))
+    }
+}
+
+// Channels of status events per path, which exist only while someone is watching
+#[derive(Clone, Default)]
+pub struct StatusHub {
+    path_to_tx: Arc<dashmap::DashMap<String, tokio::sync::broadcast::Sender<StatusEvent>>>,
+}
+
+impl StatusHub {
+    pub fn publish(&self, path: &str, event: StatusEvent) {
+        if let Some(tx) = self.path_to_tx.get(path) {
+            let _ = tx.send(event);
+        }
+    }
+
+    // Stream of events on the path in Server-Sent Events format
+    pub fn subscribe(
+        &self,
+        path: &str,
+    ) -> impl futures::Stream<Item = Result<http_body::Frame<Bytes>, anyhow::Error>> + Send {
+        let rx = self
+            .path_to_tx
+            .entry(path.to_owned())
+            .or_insert_with(|| tokio::sync::broadcast::channel(STATUS_CHANNEL_CAPACITY).0)
+            .subscribe();
+        let subscription = Subscription {
+            status_hub: self.clone(),
+            path: path.to_owned(),
+            rx,
+        };
+        futures::stream::unfold(subscription, |mut subscription| async move {
+            loop {
+                match subscription.rx.recv().await {
+                    Ok(event) => return Some((event, subscription)),
+                    // Skip events missed by the slow watcher
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .map(|event| Ok(http_body::Frame::data(event.to_sse_bytes())))
+    }
+}
+
+struct Subscription {
+    status_hub: StatusHub,
+    path: String,
+    rx: tokio::sync::broadcast::Receiver<StatusEvent>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Remove the channel when the last watcher leaves
+        // NOTE: This receiver is still counted here
+        self.status_hub
+            .path_to_tx
+            .remove_if(&self.path, |_, tx| tx.receiver_count() <= 1);
+    }
+}
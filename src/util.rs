@@ -1,14 +1,17 @@
 use core::convert::Infallible;
 use core::convert::TryFrom;
+use core::future::Future as _;
 use core::ops::Deref as _;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 use http_body_util::BodyExt as _;
-use hyper::body::Bytes;
+use hyper::body::{Buf as _, Bytes};
 use pin_project_lite::pin_project;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 
+use crate::piping_server::ClientIdentity;
+
 pub trait OptionHeaderBuilder {
     // Add optional header
     fn option_header<K, V>(self, key: K, value_opt: Option<V>) -> Self
@@ -67,6 +70,16 @@ pin_project! {
         #[pin]
         body: B,
         finish_notifier: Option<futures::channel::oneshot::Sender<()>>,
+        // Finish also when these bytes are read (e.g. Content-Length)
+        n_remaining_bytes: Option<u64>,
+    }
+}
+
+impl<B> FinishDetectableBody<B> {
+    // Finish when the specified length of data is read even if the body does not end
+    pub fn with_content_length(mut self, content_length: Option<u64>) -> Self {
+        self.n_remaining_bytes = content_length;
+        self
     }
 }
 
@@ -80,11 +93,25 @@ impl<B: http_body::Body> http_body::Body for FinishDetectableBody<B> {
     ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
         let mut this = self.project();
         match this.body.as_mut().poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let (Some(n_remaining_bytes), Some(data)) =
+                    (this.n_remaining_bytes.as_mut(), frame.data_ref())
+                {
+                    *n_remaining_bytes = n_remaining_bytes.saturating_sub(data.remaining() as u64);
+                    if *n_remaining_bytes == 0 {
+                        // Notify finish
+                        if let Some(notifier) = this.finish_notifier.take() {
+                            let _ = notifier.send(());
+                        }
+                    }
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
             // If body is finished
             Poll::Ready(None) => {
                 // Notify finish
                 if let Some(notifier) = this.finish_notifier.take() {
-                    notifier.send(()).unwrap();
+                    let _ = notifier.send(());
                 }
                 Poll::Ready(None)
             }
@@ -94,7 +121,7 @@ impl<B: http_body::Body> http_body::Body for FinishDetectableBody<B> {
 
     #[inline]
     fn is_end_stream(&self) -> bool {
-        self.body.is_end_stream()
+        self.n_remaining_bytes == Some(0) || self.body.is_end_stream()
     }
 
     #[inline]
@@ -114,15 +141,262 @@ pub fn finish_detectable_body<B: http_body::Body>(
         FinishDetectableBody {
             body,
             finish_notifier: Some(finish_notifier),
+            n_remaining_bytes: None,
         },
         finish_waiter,
     )
 }
 
+pin_project! {
+    // Body which counts the bytes of data frames
+    pub struct CountingBody<B> {
+        #[pin]
+        body: B,
+        n_bytes: Arc<std::sync::atomic::AtomicU64>,
+    }
+}
+
+impl<B: http_body::Body> http_body::Body for CountingBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.body.poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                this.n_bytes.fetch_add(
+                    data.remaining() as u64,
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+        }
+        poll
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+pub fn counting_body<B: http_body::Body>(
+    body: B,
+    n_bytes: Arc<std::sync::atomic::AtomicU64>,
+) -> CountingBody<B> {
+    CountingBody { body, n_bytes }
+}
+
+pin_project! {
+    // Body which ends with an error when aborted
+    pub struct AbortableBody<B: http_body::Body> {
+        #[pin]
+        body: B,
+        abort_waiter: Option<futures::channel::oneshot::Receiver<B::Error>>,
+        aborted: bool,
+    }
+}
+
+impl<B: http_body::Body> http_body::Body for AbortableBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if *this.aborted {
+            return Poll::Ready(None);
+        }
+        if let Some(abort_waiter) = this.abort_waiter.as_mut() {
+            match Pin::new(abort_waiter).poll(cx) {
+                Poll::Ready(Ok(err)) => {
+                    *this.aborted = true;
+                    this.abort_waiter.take();
+                    return Poll::Ready(Some(Err(err)));
+                }
+                // The body is never aborted if the aborter is dropped
+                Poll::Ready(Err(_)) => {
+                    this.abort_waiter.take();
+                }
+                Poll::Pending => {}
+            }
+        }
+        this.body.poll_frame(cx)
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.aborted || self.body.is_end_stream()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+pub fn abortable_body<B: http_body::Body>(
+    body: B,
+) -> (
+    AbortableBody<B>,
+    futures::channel::oneshot::Sender<B::Error>,
+) {
+    let (aborter, abort_waiter) = futures::channel::oneshot::channel::<B::Error>();
+    (
+        AbortableBody {
+            body,
+            abort_waiter: Some(abort_waiter),
+            aborted: false,
+        },
+        aborter,
+    )
+}
+
+pin_project! {
+    // Body which can read frames of the inner body before they are consumed
+    pub struct ReadAheadBody<B: http_body::Body> {
+        #[pin]
+        body: B,
+        frames: VecDeque<http_body::Frame<B::Data>>,
+        n_read_ahead_bytes: usize,
+        error: Option<B::Error>,
+        finished: bool,
+    }
+}
+
+impl<B: http_body::Body> ReadAheadBody<B> {
+    pub fn new(body: B) -> Self {
+        ReadAheadBody {
+            body,
+            frames: VecDeque::new(),
+            n_read_ahead_bytes: 0,
+            error: None,
+            finished: false,
+        }
+    }
+
+    // Read frames until the size of them reaches the limit or the inner body ends
+    pub fn poll_read_ahead(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        limit_bytes: usize,
+    ) -> Poll<()> {
+        let mut this = self.project();
+        while *this.n_read_ahead_bytes < limit_bytes && this.error.is_none() && !*this.finished {
+            match this.body.as_mut().poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => {
+                    if let Some(data) = frame.data_ref() {
+                        *this.n_read_ahead_bytes += data.remaining();
+                    }
+                    this.frames.push_back(frame);
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    this.error.replace(err);
+                }
+                Poll::Ready(None) => {
+                    *this.finished = true;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(())
+    }
+
+    // Whether the inner body has failed while reading ahead
+    #[inline]
+    pub fn is_errored(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+impl<B: http_body::Body> http_body::Body for ReadAheadBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if let Some(frame) = this.frames.pop_front() {
+            if let Some(data) = frame.data_ref() {
+                *this.n_read_ahead_bytes -= data.remaining();
+            }
+            return Poll::Ready(Some(Ok(frame)));
+        }
+        if let Some(err) = this.error.take() {
+            *this.finished = true;
+            return Poll::Ready(Some(Err(err)));
+        }
+        if *this.finished {
+            return Poll::Ready(None);
+        }
+        this.body.poll_frame(cx)
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.frames.is_empty()
+            && self.error.is_none()
+            && (self.finished || self.body.is_end_stream())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        let mut size_hint = self.body.size_hint();
+        let n_read_ahead_bytes = self.n_read_ahead_bytes as u64;
+        size_hint.set_lower(size_hint.lower() + n_read_ahead_bytes);
+        if let Some(upper) = size_hint.upper() {
+            size_hint.set_upper(upper + n_read_ahead_bytes);
+        }
+        size_hint
+    }
+}
+
+// Verification of client certificates
+#[derive(Clone, Debug)]
+pub struct ClientAuth {
+    // CA certificates to verify client certificates
+    pub ca_path: std::path::PathBuf,
+    // Whether a client without a certificate is rejected
+    pub required: bool,
+}
+
 pub fn load_tls_config(
     cert_path: impl AsRef<std::path::Path>,
     key_path: impl AsRef<std::path::Path> + std::fmt::Display,
 ) -> anyhow::Result<rustls::ServerConfig> {
+    load_tls_config_with_client_auth(cert_path, key_path, None)
+}
+
+pub fn load_tls_config_with_client_auth(
+    cert_path: impl AsRef<std::path::Path>,
+    key_path: impl AsRef<std::path::Path> + std::fmt::Display,
+    client_auth: Option<&ClientAuth>,
+) -> anyhow::Result<rustls::ServerConfig> {
+    let (certs, private_key) = load_certs_and_private_key(cert_path.as_ref(), key_path.as_ref())?;
+    let mut config = tls_config_builder(client_auth)?.with_single_cert(certs, private_key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+fn load_certs_and_private_key(
+    cert_path: &std::path::Path,
+    key_path: &std::path::Path,
+) -> anyhow::Result<(
+    Vec<rustls::pki_types::CertificateDer<'static>>,
+    rustls::pki_types::PrivateKeyDer<'static>,
+)> {
     let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(&mut std::fs::File::open(
         cert_path,
     )?))
@@ -130,87 +404,424 @@ pub fn load_tls_config(
     let private_key = rustls_pemfile::private_key(&mut std::io::BufReader::new(
         &mut std::fs::File::open(key_path)?,
     ))?
-    .expect("private key not found");
-    let mut config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, private_key)?;
-    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-    Ok(config)
+    .ok_or_else(|| anyhow::anyhow!("private key not found in {}", key_path.display()))?;
+    Ok((certs, private_key))
+}
+
+fn tls_config_builder(
+    client_auth: Option<&ClientAuth>,
+) -> anyhow::Result<rustls::ConfigBuilder<rustls::ServerConfig, rustls::server::WantsServerCert>> {
+    let builder = rustls::ServerConfig::builder();
+    let Some(client_auth) = client_auth else {
+        return Ok(builder.with_no_client_auth());
+    };
+    let mut roots = rustls::RootCertStore::empty();
+    for ca_cert in rustls_pemfile::certs(&mut std::io::BufReader::new(&mut std::fs::File::open(
+        &client_auth.ca_path,
+    )?)) {
+        roots.add(ca_cert?)?;
+    }
+    let verifier_builder = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots));
+    let verifier = if client_auth.required {
+        verifier_builder.build()?
+    } else {
+        verifier_builder.allow_unauthenticated().build()?
+    };
+    Ok(builder.with_client_cert_verifier(verifier))
 }
 
 pub fn hot_reload_tls_cfg(
     tokio_handle: tokio::runtime::Handle,
     cert_path: impl AsRef<std::path::Path> + Send + Sync + 'static,
     key_path: impl AsRef<std::path::Path> + Send + Sync + std::fmt::Display + 'static,
+) -> Arc<tokio::sync::RwLock<Arc<rustls::ServerConfig>>> {
+    hot_reload_tls_cfg_with_client_auth(tokio_handle, cert_path, key_path, None)
+}
+
+// Reload the TLS config when the certificate, the private key or the CA certificates are changed
+pub fn hot_reload_tls_cfg_with_client_auth(
+    tokio_handle: tokio::runtime::Handle,
+    cert_path: impl AsRef<std::path::Path> + Send + Sync + 'static,
+    key_path: impl AsRef<std::path::Path> + Send + Sync + std::fmt::Display + 'static,
+    client_auth: Option<ClientAuth>,
 ) -> Arc<tokio::sync::RwLock<Arc<rustls::ServerConfig>>> {
     let cert_path = Arc::new(cert_path);
     let key_path = Arc::new(key_path);
     let tls_cfg_rwlock_arc = Arc::new(tokio::sync::RwLock::new(Arc::new(
-        load_tls_config(cert_path.deref(), key_path.deref()).unwrap(),
+        load_tls_config_with_client_auth(cert_path.deref(), key_path.deref(), client_auth.as_ref())
+            .unwrap(),
     )));
 
+    let mut paths = vec![
+        cert_path.deref().as_ref().to_path_buf(),
+        key_path.deref().as_ref().to_path_buf(),
+    ];
+    if let Some(client_auth) = &client_auth {
+        paths.push(client_auth.ca_path.clone());
+    }
+    watch_files(paths, {
+        let tls_cfg_rwlock = tls_cfg_rwlock_arc.clone();
+        move |_changed_paths| {
+            let tls_cfg = match load_tls_config_with_client_auth(
+                cert_path.deref(),
+                key_path.deref(),
+                client_auth.as_ref(),
+            ) {
+                Ok(x) => x,
+                Err(e) => {
+                    log::error!("Failed to load new certificates: {e:?}");
+                    return;
+                }
+            };
+            let tls_cfg_rwlock = tls_cfg_rwlock.clone();
+            tokio_handle.spawn(async move {
+                *(tls_cfg_rwlock.write().await) = Arc::new(tls_cfg);
+                log::info!("Successfully new certificates loaded");
+            });
+        }
+    });
+
+    tls_cfg_rwlock_arc
+}
+
+// Call the handler with the changed ones of the paths in a dedicated thread
+// NOTE: The parent directories are watched to detect a replaced symbolic link (e.g. renewal by certbot)
+fn watch_files(
+    paths: Vec<std::path::PathBuf>,
+    mut on_change: impl FnMut(&[std::path::PathBuf]) + Send + 'static,
+) {
     // NOTE: tokio::spawn() blocks servers in some environment because of `loop {}`
-    std::thread::spawn::<_, Result<(), notify::Error>>({
+    std::thread::spawn::<_, Result<(), notify::Error>>(move || {
+        use notify::Watcher;
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let mut watcher: notify::RecommendedWatcher = notify::Watcher::new(
+            tx,
+            notify::Config::default().with_poll_interval(std::time::Duration::from_secs(5)),
+        )?;
+
+        let mut watched_paths = Vec::with_capacity(paths.len());
+        for path in paths {
+            let canonical_path = canonicalize_parent(&path).map_err(notify::Error::io)?;
+            if let Some(dir) = canonical_path.parent() {
+                watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+            }
+            watched_paths.push((path, canonical_path));
+        }
+
+        loop {
+            let received: Result<_, _> = match rx.recv() {
+                Ok(x) => x,
+                Err(e) => {
+                    log::error!("Watch certificates error: {e:?}");
+                    break;
+                }
+            };
+            let event: notify::Event = match received {
+                Ok(x) => x,
+                Err(e) => {
+                    log::error!("Watch certificates error: {e:?}");
+                    continue;
+                }
+            };
+            let changed_paths: Vec<std::path::PathBuf> = watched_paths
+                .iter()
+                .filter(|(_, canonical_path)| {
+                    event.paths.iter().any(|path| {
+                        canonicalize_parent(path).map_or(false, |path| &path == canonical_path)
+                    })
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            if !changed_paths.is_empty() {
+                on_change(&changed_paths);
+            }
+        }
+        Ok(())
+    });
+}
+
+// Path with the parent directory canonicalized, where the symbolic link of the file is not resolved
+fn canonicalize_parent(path: &std::path::Path) -> std::io::Result<std::path::PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{}: not a file path", path.display()),
+        )
+    })?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    Ok(std::fs::canonicalize(dir)?.join(file_name))
+}
+
+// Certificate for a host name selected by SNI, parsed from "<host>=<cert path>,<key path>"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostCert {
+    pub host: String,
+    pub cert_path: std::path::PathBuf,
+    pub key_path: std::path::PathBuf,
+}
+
+impl std::str::FromStr for HostCert {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((host, (cert_path, key_path))) = s
+            .split_once('=')
+            .and_then(|(host, paths)| Some((host, paths.split_once(',')?)))
+        else {
+            anyhow::bail!("should be <host>=<cert path>,<key path> but {s:?}");
+        };
+        if host.is_empty() || cert_path.is_empty() || key_path.is_empty() {
+            anyhow::bail!("should be <host>=<cert path>,<key path> but {s:?}");
+        }
+        Ok(HostCert {
+            host: host.to_ascii_lowercase(),
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        })
+    }
+}
+
+// Certificates of <host>.crt and <host>.key pairs in the directory
+pub fn host_certs_in_dir(dir: impl AsRef<std::path::Path>) -> anyhow::Result<Vec<HostCert>> {
+    let mut host_certs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let cert_path = entry?.path();
+        if cert_path.extension() != Some("crt".as_ref()) {
+            continue;
+        }
+        let Some(host) = cert_path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let key_path = cert_path.with_extension("key");
+        if !key_path.is_file() {
+            anyhow::bail!(
+                "{} not found for {}",
+                key_path.display(),
+                cert_path.display()
+            );
+        }
+        host_certs.push(HostCert {
+            host: host.to_ascii_lowercase(),
+            cert_path,
+            key_path,
+        });
+    }
+    host_certs.sort_by(|a, b| a.host.cmp(&b.host));
+    Ok(host_certs)
+}
+
+pub fn load_certified_key(
+    cert_path: impl AsRef<std::path::Path>,
+    key_path: impl AsRef<std::path::Path>,
+) -> anyhow::Result<rustls::sign::CertifiedKey> {
+    let (certs, private_key) = load_certs_and_private_key(cert_path.as_ref(), key_path.as_ref())?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&private_key)?;
+    Ok(rustls::sign::CertifiedKey::new(certs, signing_key))
+}
+
+// Resolver of the certificate for the host name in SNI, falling back to the default certificate
+#[derive(Debug, Default)]
+pub struct SniCertResolver {
+    host_certs: dashmap::DashMap<String, Arc<rustls::sign::CertifiedKey>>,
+    default_cert: std::sync::RwLock<Option<Arc<rustls::sign::CertifiedKey>>>,
+}
+
+impl SniCertResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_host_cert(&self, host: &str, cert: rustls::sign::CertifiedKey) {
+        self.host_certs
+            .insert(host.to_ascii_lowercase(), Arc::new(cert));
+    }
+
+    pub fn set_default_cert(&self, cert: rustls::sign::CertifiedKey) {
+        *self.default_cert.write().unwrap() = Some(Arc::new(cert));
+    }
+}
+
+impl rustls::server::ResolvesServerCert for SniCertResolver {
+    fn resolve(
+        &self,
+        client_hello: rustls::server::ClientHello,
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|host| {
+                self.host_certs
+                    .get(&host.to_ascii_lowercase())
+                    .map(|cert| cert.clone())
+            })
+            .or_else(|| self.default_cert.read().unwrap().clone())
+    }
+}
+
+fn load_sni_tls_config(
+    resolver: Arc<SniCertResolver>,
+    client_auth: Option<&ClientAuth>,
+) -> anyhow::Result<rustls::ServerConfig> {
+    let mut config = tls_config_builder(client_auth)?.with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+// TLS config selecting certificates by SNI, where each certificate is reloaded independently when its files are changed
+pub fn hot_reload_sni_tls_cfg(
+    tokio_handle: tokio::runtime::Handle,
+    default_cert: Option<(std::path::PathBuf, std::path::PathBuf)>,
+    host_certs: Vec<HostCert>,
+    client_auth: Option<ClientAuth>,
+) -> anyhow::Result<Arc<tokio::sync::RwLock<Arc<rustls::ServerConfig>>>> {
+    // NOTE: The host is None for the default certificate
+    let mut certs: Vec<(Option<String>, std::path::PathBuf, std::path::PathBuf)> = Vec::new();
+    if let Some((cert_path, key_path)) = default_cert {
+        certs.push((None, cert_path, key_path));
+    }
+    for host_cert in host_certs {
+        certs.push((
+            Some(host_cert.host),
+            host_cert.cert_path,
+            host_cert.key_path,
+        ));
+    }
+    let resolver = Arc::new(SniCertResolver::new());
+    for (host, cert_path, key_path) in &certs {
+        let cert = load_certified_key(cert_path, key_path)
+            .map_err(|e| anyhow::anyhow!("{}, {}: {e}", cert_path.display(), key_path.display()))?;
+        match host {
+            Some(host) => resolver.set_host_cert(host, cert),
+            None => resolver.set_default_cert(cert),
+        }
+    }
+    let tls_cfg_rwlock_arc = Arc::new(tokio::sync::RwLock::new(Arc::new(load_sni_tls_config(
+        resolver.clone(),
+        client_auth.as_ref(),
+    )?)));
+
+    let mut paths = Vec::new();
+    for (_, cert_path, key_path) in &certs {
+        paths.push(cert_path.clone());
+        paths.push(key_path.clone());
+    }
+    if let Some(client_auth) = &client_auth {
+        paths.push(client_auth.ca_path.clone());
+    }
+    watch_files(paths, {
         let tls_cfg_rwlock = tls_cfg_rwlock_arc.clone();
-        move || {
-            use notify::Watcher;
-            let (tx, rx) = std::sync::mpsc::channel();
-
-            let mut watcher: notify::RecommendedWatcher = notify::Watcher::new(
-                tx,
-                notify::Config::default().with_poll_interval(std::time::Duration::from_secs(5)),
-            )?;
-
-            watcher.watch(
-                cert_path.deref().as_ref(),
-                notify::RecursiveMode::NonRecursive,
-            )?;
-            watcher.watch(
-                key_path.deref().as_ref(),
-                notify::RecursiveMode::NonRecursive,
-            )?;
-
-            loop {
-                let received: Result<_, _> = match rx.recv() {
-                    Ok(x) => x,
-                    Err(e) => {
-                        log::error!("Watch certificates error: {e:?}");
-                        break;
-                    }
-                };
-                let _event: notify::Event = match received {
-                    Ok(x) => x,
-                    Err(e) => {
-                        log::error!("Watch certificates error: {e:?}");
-                        continue;
-                    }
-                };
-                let tls_cfg = match load_tls_config(cert_path.deref(), key_path.deref()) {
+        move |changed_paths| {
+            for (host, cert_path, key_path) in &certs {
+                if !changed_paths.contains(cert_path) && !changed_paths.contains(key_path) {
+                    continue;
+                }
+                let host_name = host.as_deref().unwrap_or("default");
+                let cert = match load_certified_key(cert_path, key_path) {
                     Ok(x) => x,
                     Err(e) => {
-                        log::error!("Failed to load new certificates: {e:?}");
+                        log::error!("Failed to load new certificate for {host_name}: {e:?}");
                         continue;
                     }
                 };
-                let tls_cfg_rwlock = tls_cfg_rwlock.clone();
-                tokio_handle.spawn(async move {
-                    *(tls_cfg_rwlock.write().await) = Arc::new(tls_cfg);
-                    log::info!("Successfully new certificates loaded");
-                });
+                match host {
+                    Some(host) => resolver.set_host_cert(host, cert),
+                    None => resolver.set_default_cert(cert),
+                }
+                log::info!("Successfully new certificate for {host_name} loaded");
             }
-            Ok(())
+            let Some(client_auth) = &client_auth else {
+                return;
+            };
+            if !changed_paths.contains(&client_auth.ca_path) {
+                return;
+            }
+            let tls_cfg = match load_sni_tls_config(resolver.clone(), Some(client_auth)) {
+                Ok(x) => x,
+                Err(e) => {
+                    log::error!("Failed to load new CA certificates: {e:?}");
+                    return;
+                }
+            };
+            let tls_cfg_rwlock = tls_cfg_rwlock.clone();
+            tokio_handle.spawn(async move {
+                *(tls_cfg_rwlock.write().await) = Arc::new(tls_cfg);
+                log::info!("Successfully new CA certificates loaded");
+            });
         }
     });
 
-    return tls_cfg_rwlock_arc;
+    Ok(tls_cfg_rwlock_arc)
+}
+
+// Identity in the client certificate verified in the TLS handshake
+pub fn client_identity(conn: &rustls::ServerConnection) -> Option<ClientIdentity> {
+    let cert = webpki::EndEntityCert::try_from(conn.peer_certificates()?.first()?).ok()?;
+    Some(ClientIdentity {
+        common_name: der_common_name(cert.subject()),
+        dns_names: cert.valid_dns_names().map(|name| name.to_owned()).collect(),
+    })
+}
+
+// Common name (OID 2.5.4.3) in the DER-encoded RDNSequence of a certificate subject
+fn der_common_name(rdn_sequence: &[u8]) -> Option<String> {
+    const TAG_SET: u8 = 0x31;
+    const TAG_SEQUENCE: u8 = 0x30;
+    const TAG_OID: u8 = 0x06;
+    const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+    let mut rdns = rdn_sequence;
+    while !rdns.is_empty() {
+        let (TAG_SET, mut attributes, rest) = der_read_tlv(rdns)? else {
+            return None;
+        };
+        rdns = rest;
+        while !attributes.is_empty() {
+            let (TAG_SEQUENCE, attribute, rest) = der_read_tlv(attributes)? else {
+                return None;
+            };
+            attributes = rest;
+            let (TAG_OID, oid, value) = der_read_tlv(attribute)? else {
+                return None;
+            };
+            if oid == OID_COMMON_NAME {
+                // NOTE: The value is a string such as UTF8String and PrintableString
+                let (_, value, _) = der_read_tlv(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+// Read a DER element and return its tag, its value and the rest of the input
+fn der_read_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first_len_byte, mut input) = input.split_first()?;
+    let len = if first_len_byte < 0x80 {
+        first_len_byte as usize
+    } else {
+        let n_len_bytes = (first_len_byte & 0x7f) as usize;
+        if n_len_bytes == 0 || n_len_bytes > std::mem::size_of::<usize>() {
+            return None;
+        }
+        let (len_bytes, rest) = (input.get(..n_len_bytes)?, &input[n_len_bytes..]);
+        input = rest;
+        len_bytes
+            .iter()
+            .fold(0usize, |len, &byte| (len << 8) | byte as usize)
+    };
+    let value = input.get(..len)?;
+    Some((tag, value, &input[len..]))
 }
 
 pub fn query_param_to_hash_map(query: Option<&str>) -> HashMap<String, String> {
-    return match query {
+    match query {
         Some(query) => serde_urlencoded::from_str::<HashMap<String, String>>(query)
             .unwrap_or_else(|_| HashMap::new()),
         None => HashMap::new(),
-    };
+    }
 }
 
 #[inline]
@@ -226,3 +837,97 @@ pub fn empty_body<E>() -> impl http_body::Body<Data = Bytes, Error = E> {
     http_body_util::Empty::<Bytes>::new()
         .map_err(|_: Infallible| unreachable!("Error of Empty::new() should be Infallible"))
 }
+
+// Format the number of bytes in a human-readable form (e.g. "1.2 GiB")
+pub fn format_bytes(n_bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if n_bytes < 1024.0 {
+        return format!("{n_bytes:.0} B");
+    }
+    let mut value = n_bytes / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{value:.1} {unit}")
+}
+
+// Parse a duration such as "5s", "500ms", "1m", "1h" or "5" (seconds)
+pub fn parse_duration(s: &str) -> anyhow::Result<std::time::Duration> {
+    let (value, unit_secs) = if let Some(value) = s.strip_suffix("ms") {
+        (value, 0.001)
+    } else if let Some(value) = s.strip_suffix('s') {
+        (value, 1.0)
+    } else if let Some(value) = s.strip_suffix('m') {
+        (value, 60.0)
+    } else if let Some(value) = s.strip_suffix('h') {
+        (value, 60.0 * 60.0)
+    } else {
+        (s, 1.0)
+    };
+    let secs: f64 = value.parse::<f64>()? * unit_secs;
+    // NOTE: Duration::from_secs_f64() panics with a negative, infinite or too large value
+    if !(0.0..u64::MAX as f64).contains(&secs) {
+        anyhow::bail!("invalid duration: {s}");
+    }
+    Ok(std::time::Duration::from_secs_f64(secs))
+}
+
+// Buffer keeping the last bytes up to the capacity with their offsets
+pub struct BytesRingBuffer {
+    capacity: usize,
+    chunks: VecDeque<Bytes>,
+    len: usize,
+    end_offset: u64,
+}
+
+impl BytesRingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        BytesRingBuffer {
+            capacity,
+            chunks: VecDeque::new(),
+            len: 0,
+            end_offset: 0,
+        }
+    }
+
+    pub fn push(&mut self, bytes: Bytes) {
+        self.end_offset += bytes.len() as u64;
+        self.len += bytes.len();
+        self.chunks.push_back(bytes);
+        while self.len > self.capacity {
+            let front = self.chunks.front_mut().unwrap();
+            let n_overflowed_bytes = self.len - self.capacity;
+            if front.len() <= n_overflowed_bytes {
+                self.len -= front.len();
+                self.chunks.pop_front();
+            } else {
+                front.advance(n_overflowed_bytes);
+                self.len -= n_overflowed_bytes;
+            }
+        }
+    }
+
+    // Bytes from the offset to the end (None if the offset is out of the buffer)
+    pub fn bytes_from(&self, offset: u64) -> Option<Vec<Bytes>> {
+        let start_offset = self.end_offset - self.len as u64;
+        if offset < start_offset || offset > self.end_offset {
+            return None;
+        }
+        let mut n_skipped_bytes = (offset - start_offset) as usize;
+        let mut chunks = Vec::new();
+        for chunk in &self.chunks {
+            if n_skipped_bytes >= chunk.len() {
+                n_skipped_bytes -= chunk.len();
+                continue;
+            }
+            chunks.push(chunk.slice(n_skipped_bytes..));
+            n_skipped_bytes = 0;
+        }
+        Some(chunks)
+    }
+}
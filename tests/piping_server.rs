@@ -4,7 +4,7 @@ use specit::tokio_it as it;
 
 use futures::FutureExt as _;
 use hyper::body::Bytes;
-use piping_server::piping_server::PipingServer;
+use piping_server::piping_server::{PipingServer, PipingServerConfig, RemoteAddr};
 use std::net::SocketAddr;
 use std::time;
 
@@ -22,16 +22,28 @@ async fn read_all_body(body: hyper::body::Incoming) -> anyhow::Result<Vec<u8>> {
     let mut stream = http_body_util::BodyStream::new(body);
 
     let mut all_bytes: Vec<u8> = Vec::new();
-    loop {
-        if let Some(Ok(frame)) = stream.next().await {
-            all_bytes.append(&mut frame.into_data().unwrap().to_vec());
-        } else {
-            break;
-        }
+    while let Some(Ok(frame)) = stream.next().await {
+        all_bytes.append(&mut frame.into_data().unwrap().to_vec());
     }
     Ok(all_bytes)
 }
 
+// Read body until it ends or fails and return the read bytes and the result
+async fn read_body_until_end(
+    mut body: hyper::body::Incoming,
+) -> (Vec<u8>, Result<(), hyper::Error>) {
+    use http_body_util::BodyExt as _;
+
+    let mut all_bytes: Vec<u8> = Vec::new();
+    while let Some(frame) = body.frame().await {
+        match frame {
+            Ok(frame) => all_bytes.extend_from_slice(&frame.into_data().unwrap()),
+            Err(err) => return (all_bytes, Err(err)),
+        }
+    }
+    (all_bytes, Ok(()))
+}
+
 #[inline]
 fn full_body<B: Into<Bytes>>(b: B) -> http_body_util::Full<Bytes> {
     http_body_util::Full::new(b.into())
@@ -42,51 +54,75 @@ fn empty_body() -> http_body_util::Empty<Bytes> {
     http_body_util::Empty::<Bytes>::new()
 }
 
+// Create a new empty directory for a test
+fn create_temp_dir() -> std::path::PathBuf {
+    let mut random_bytes = [0u8; 8];
+    getrandom::getrandom(&mut random_bytes).unwrap();
+    let dir_name: String = random_bytes.iter().map(|b| format!("{b:02x}")).collect();
+    let dir = std::env::temp_dir().join(format!("piping-server-test-{dir_name}"));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 struct Serve {
     addr: SocketAddr,
+    piping_server: PipingServer,
     shutdown_tx: oneshot::Sender<()>,
     shutdown_finished_rx: oneshot::Receiver<()>,
 }
 
 // Serve Piping Server on available port
 async fn serve() -> Serve {
-    let piping_server = PipingServer::new();
+    serve_with_config(PipingServerConfig::default()).await
+}
 
+// Serve Piping Server with the config on available port
+async fn serve_with_config(config: PipingServerConfig) -> Serve {
+    serve_piping_server(PipingServer::try_with_config(config).unwrap()).await
+}
+
+// Serve the Piping Server on available port
+async fn serve_piping_server(piping_server: PipingServer) -> Serve {
     let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
     let (shutdown_finished_tx, shutdown_finished_rx) = oneshot::channel::<()>();
 
-    let tcp_listener = tokio::net::TcpListener::bind::<(_, u16)>(("127.0.0.1", 0).into())
+    let tcp_listener = tokio::net::TcpListener::bind::<(_, u16)>(("127.0.0.1", 0))
         .await
         .unwrap();
     let addr = tcp_listener.local_addr().unwrap();
 
-    tokio::spawn(async move {
+    tokio::spawn({
         let piping_server = piping_server.clone();
-        let piping_server_service =
-            hyper::service::service_fn(move |req| piping_server.clone().handle(false, req));
-
-        loop {
-            let accept_fut = tcp_listener.accept().fuse();
-            futures::pin_mut!(accept_fut);
-            let (stream, _) = futures::select! {
-                accepted = accept_fut => accepted.unwrap(),
-                _ = shutdown_rx => break,
-            };
-            let piping_server_service = piping_server_service.clone();
-            tokio::task::spawn(async move {
-                hyper_util::server::conn::auto::Builder::new(
-                    hyper_util::rt::tokio::TokioExecutor::new(),
-                )
-                .serve_connection(hyper_util::rt::TokioIo::new(stream), piping_server_service)
-                .await
-                .unwrap()
-            });
+        async move {
+            loop {
+                let accept_fut = tcp_listener.accept().fuse();
+                futures::pin_mut!(accept_fut);
+                let (stream, remote_addr) = futures::select! {
+                    accepted = accept_fut => accepted.unwrap(),
+                    _ = shutdown_rx => break,
+                };
+                let piping_server = piping_server.clone();
+                let piping_server_service =
+                    hyper::service::service_fn(move |mut req: hyper::Request<_>| {
+                        req.extensions_mut().insert(RemoteAddr(remote_addr));
+                        piping_server.clone().handle(false, req)
+                    });
+                tokio::task::spawn(async move {
+                    hyper_util::server::conn::auto::Builder::new(
+                        hyper_util::rt::tokio::TokioExecutor::new(),
+                    )
+                    .serve_connection(hyper_util::rt::TokioIo::new(stream), piping_server_service)
+                    .await
+                    .unwrap()
+                });
+            }
+            shutdown_finished_tx.send(()).unwrap();
         }
-        shutdown_finished_tx.send(()).unwrap();
     });
 
     Serve {
         addr,
+        piping_server,
         shutdown_tx,
         shutdown_finished_rx,
     }
@@ -125,6 +161,176 @@ where
     Ok(res)
 }
 
+async fn http2_request<B>(
+    request: http::Request<B>,
+) -> anyhow::Result<http::Response<hyper::body::Incoming>>
+where
+    B: http_body::Body + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let host = request.uri().host().unwrap();
+    let port = request.uri().port_u16().unwrap_or(80);
+    let address = format!("{}:{}", host, port);
+    let stream = tokio::net::TcpStream::connect(address).await?;
+    let (mut sender, conn) = hyper::client::conn::http2::handshake(
+        hyper_util::rt::tokio::TokioExecutor::new(),
+        hyper_util::rt::TokioIo::new(stream),
+    )
+    .await?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            println!("client connection failed: {:?}", err);
+        }
+    });
+    let res = sender.send_request(request).await?;
+    Ok(res)
+}
+
+// Path of a file in tests/fixtures, where the CA signs the server certificate for localhost and the client certificate of CN=alice
+fn fixture_path(file_name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(file_name)
+}
+
+// Serve the Piping Server over HTTPS with the TLS config on available port
+async fn serve_https(
+    piping_server: PipingServer,
+    tls_config: rustls::ServerConfig,
+) -> anyhow::Result<SocketAddr> {
+    let tcp_listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await?;
+    let addr = tcp_listener.local_addr()?;
+    let tls_acceptor = tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(tls_config));
+    tokio::spawn(async move {
+        loop {
+            let (stream, remote_addr) = tcp_listener.accept().await.unwrap();
+            let Ok(stream) = tls_acceptor.accept(stream).await else {
+                continue;
+            };
+            let client_identity = piping_server::util::client_identity(stream.get_ref().1);
+            let piping_server = piping_server.clone();
+            let piping_server_service =
+                hyper::service::service_fn(move |mut req: hyper::Request<_>| {
+                    req.extensions_mut().insert(RemoteAddr(remote_addr));
+                    if let Some(client_identity) = client_identity.clone() {
+                        req.extensions_mut().insert(client_identity);
+                    }
+                    piping_server.clone().handle(true, req)
+                });
+            tokio::spawn(
+                hyper_util::server::conn::auto::Builder::new(
+                    hyper_util::rt::tokio::TokioExecutor::new(),
+                )
+                .serve_connection_with_upgrades(
+                    hyper_util::rt::TokioIo::new(stream),
+                    piping_server_service,
+                )
+                .into_owned(),
+            );
+        }
+    });
+    Ok(addr)
+}
+
+// Process of the piping-server binary killed when dropped
+struct ServerProcess(std::process::Child);
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Run the piping-server binary with the arguments and wait until the port is listened
+async fn run_server_process(args: &[String], port: u16) -> anyhow::Result<ServerProcess> {
+    run_server_process_with_stderr(args, port, std::process::Stdio::null()).await
+}
+
+// Run the piping-server binary writing logs to the stderr and wait until the port is listened
+async fn run_server_process_with_stderr(
+    args: &[String],
+    port: u16,
+    stderr: std::process::Stdio,
+) -> anyhow::Result<ServerProcess> {
+    let server_process = ServerProcess(
+        std::process::Command::new(env!("CARGO_BIN_EXE_piping-server"))
+            .args(args)
+            .stdout(std::process::Stdio::null())
+            .stderr(stderr)
+            .spawn()?,
+    );
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_ok()
+        {
+            return Ok(server_process);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    anyhow::bail!("piping-server did not listen on {port}")
+}
+
+fn available_port() -> u16 {
+    std::net::TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+// Send a request over HTTPS trusting the test CA with the client certificate if specified
+// NOTE: The host in the URI is used as the server name and the connection is made to 127.0.0.1
+async fn https_request<B>(
+    request: http::Request<B>,
+    client_cert_name: Option<&str>,
+) -> anyhow::Result<http::Response<hyper::body::Incoming>>
+where
+    B: http_body::Body + Send + Sync + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let load_certs = |file_name: &str| {
+        rustls_pemfile::certs(&mut std::io::BufReader::new(std::fs::File::open(
+            fixture_path(file_name),
+        )?))
+        .collect::<Result<Vec<_>, _>>()
+    };
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in load_certs("ca.crt")? {
+        roots.add(cert)?;
+    }
+    let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+    let client_config = match client_cert_name {
+        Some(name) => {
+            let private_key = rustls_pemfile::private_key(&mut std::io::BufReader::new(
+                std::fs::File::open(fixture_path(&format!("{name}.key")))?,
+            ))?
+            .unwrap();
+            builder.with_client_auth_cert(load_certs(&format!("{name}.crt"))?, private_key)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let server_name = rustls::pki_types::ServerName::try_from(request.uri().host().unwrap())?;
+    let port = request.uri().port_u16().unwrap();
+    let stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await?;
+    let stream = tokio_rustls::TlsConnector::from(std::sync::Arc::new(client_config))
+        .connect(server_name.to_owned(), stream)
+        .await?;
+    let (mut sender, conn) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            println!("client connection failed: {:?}", err);
+        }
+    });
+    let res = sender.send_request(request).await?;
+    Ok(res)
+}
+
 #[it("should return index page")]
 async fn f() -> anyhow::Result<()> {
     let serve: Serve = serve().await;
@@ -188,7 +394,7 @@ async fn f() -> anyhow::Result<()> {
     // Should disable JavaScript and allow CSS with nonce
     assert!(Regex::new(r"^default-src 'none'; style-src 'nonce-.+'$")
         .unwrap()
-        .is_match(&get_header_value(&res_parts.headers, "content-security-policy").unwrap()));
+        .is_match(get_header_value(&res_parts.headers, "content-security-policy").unwrap()));
 
     serve.shutdown().await?;
     Ok(())
@@ -386,13 +592,13 @@ async fn f() -> anyhow::Result<()> {
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-allow-methods"),
-        Some("GET, HEAD, POST, PUT, OPTIONS")
+        Some("GET, HEAD, POST, PUT, PATCH, OPTIONS")
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-allow-headers")
             .unwrap()
             .to_lowercase(),
-        "content-type, content-disposition, x-piping".to_owned()
+        "content-type, content-disposition, x-piping, x-piping-progress, content-range, upload-offset, upload-complete".to_owned()
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-expose-headers")
@@ -432,7 +638,7 @@ async fn f() -> anyhow::Result<()> {
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-allow-methods"),
-        Some("GET, HEAD, POST, PUT, OPTIONS")
+        Some("GET, HEAD, POST, PUT, PATCH, OPTIONS")
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-allow-private-network"),
@@ -442,7 +648,7 @@ async fn f() -> anyhow::Result<()> {
         get_header_value(&parts.headers, "access-control-allow-headers")
             .unwrap()
             .to_lowercase(),
-        "content-type, content-disposition, x-piping".to_owned()
+        "content-type, content-disposition, x-piping, x-piping-progress, content-range, upload-offset, upload-complete".to_owned()
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-max-age"),
@@ -482,7 +688,7 @@ async fn f() -> anyhow::Result<()> {
     Ok(())
 }
 
-#[it("should reject POST and PUT with Content-Range")]
+#[it("should reject POST and PUT with Content-Range without an upload to resume")]
 async fn f() -> anyhow::Result<()> {
     let serve: Serve = serve().await;
 
@@ -653,7 +859,8 @@ async fn f() -> anyhow::Result<()> {
 
     let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
 
-    {
+    // NOTE: The response body is kept to keep the first sender connected
+    let _first_send_res_body = {
         let send_body_str = "this is a content";
         let send_req = hyper::Request::builder()
             .method(hyper::Method::POST)
@@ -662,7 +869,7 @@ async fn f() -> anyhow::Result<()> {
             .body(full_body(send_body_str))?;
 
         let send_res = http_request(send_req).await?;
-        let (send_res_parts, _send_res_body) = send_res.into_parts();
+        let (send_res_parts, send_res_body) = send_res.into_parts();
         assert_eq!(send_res_parts.status, http::StatusCode::OK);
         assert_eq!(
             get_header_value(&send_res_parts.headers, "content-type"),
@@ -672,7 +879,8 @@ async fn f() -> anyhow::Result<()> {
             get_header_value(&send_res_parts.headers, "access-control-allow-origin"),
             Some("*")
         );
-    }
+        send_res_body
+    };
 
     {
         let send_body_str = "this is a content";
@@ -858,54 +1066,147 @@ async fn f() -> anyhow::Result<()> {
     Ok(())
 }
 
-#[it("should reject n > 1 because not supported yet")]
+#[it("should handle multiple receivers (sender: O, receivers: O)")]
 async fn f() -> anyhow::Result<()> {
     let serve: Serve = serve().await;
 
-    {
-        let send_body_str = "this is a content";
-        let send_req = hyper::Request::builder()
-            .method(hyper::Method::POST)
-            .header("Content-Type", "text/plain")
-            .uri(format!("http://{}/mypath?n=2", serve.addr))
-            .body(full_body(send_body_str))?;
+    let uri = format!("http://{}/mypath?n=3", serve.addr).parse::<http::Uri>()?;
 
-        let send_res = http_request(send_req).await?;
-        let (send_res_parts, _send_res_body) = send_res.into_parts();
-        assert_eq!(send_res_parts.status, http::StatusCode::BAD_REQUEST);
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "text/plain")
+        .uri(uri.clone())
+        .body(full_body(send_body_str))?;
+    let send_res = http_request(send_req).await?;
+    let (send_res_parts, send_res_body) = send_res.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+
+    let mut get_res_join_handles = Vec::new();
+    for _ in 0..3 {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(uri.clone())
+            .body(empty_body())?;
+        get_res_join_handles.push(tokio::spawn(http_request(get_req)));
+    }
+
+    for get_res_join_handle in get_res_join_handles {
+        let (parts, body) = get_res_join_handle.await??.into_parts();
+        assert_eq!(parts.status, http::StatusCode::OK);
         assert_eq!(
-            get_header_value(&send_res_parts.headers, "content-type"),
+            get_header_value(&parts.headers, "content-type"),
             Some("text/plain")
         );
         assert_eq!(
-            get_header_value(&send_res_parts.headers, "access-control-allow-origin"),
-            Some("*")
+            get_header_value(&parts.headers, "content-length"),
+            Some(send_body_str.len().to_string().as_str())
         );
+        let all_bytes: Vec<u8> = read_all_body(body).await?;
+        assert_eq!(all_bytes, send_body_str.to_owned().into_bytes());
     }
 
-    {
+    let send_res_body_string = String::from_utf8(read_all_body(send_res_body).await?)?;
+    assert!(send_res_body_string.contains("[INFO] Waiting for 3 receiver(s)...\n"));
+    assert!(send_res_body_string.contains("[INFO] Start sending to 3 receiver(s)...\n"));
+    let receipt_regex =
+        Regex::new(r"\[INFO\] Sent successfully! 17 bytes delivered in \d+\.\d{3}s \(.+/s\)\.\n$")?;
+    assert!(
+        receipt_regex.is_match(&send_res_body_string),
+        "{send_res_body_string}"
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should handle multiple receivers (receivers: O, sender: O)")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath?n=2", serve.addr).parse::<http::Uri>()?;
+
+    let mut get_res_join_handles = Vec::new();
+    for _ in 0..2 {
         let get_req = hyper::Request::builder()
             .method(hyper::Method::GET)
-            .uri(format!("http://{}/mypath?n=2", serve.addr))
+            .uri(uri.clone())
             .body(empty_body())?;
+        get_res_join_handles.push(tokio::spawn(http_request(get_req)));
+    }
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
 
-        let get_res = http_request(get_req).await?;
-        let (get_res_parts, _get_res_body) = get_res.into_parts();
-        assert_eq!(get_res_parts.status, http::StatusCode::BAD_REQUEST);
-        assert_eq!(
-            get_header_value(&get_res_parts.headers, "content-type"),
-            Some("text/plain")
-        );
-        assert_eq!(
-            get_header_value(&get_res_parts.headers, "access-control-allow-origin"),
-            Some("*")
-        );
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(full_body(send_body_str))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    for get_res_join_handle in get_res_join_handles {
+        let (parts, body) = get_res_join_handle.await??.into_parts();
+        assert_eq!(parts.status, http::StatusCode::OK);
+        let all_bytes: Vec<u8> = read_all_body(body).await?;
+        assert_eq!(all_bytes, send_body_str.to_owned().into_bytes());
     }
 
     serve.shutdown_tx.send(()).expect("shutdown failed");
     Ok(())
 }
 
+#[it("should reject a receiver with n different from the sender's n")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?n=2", serve.addr))
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?n=3", serve.addr))
+        .body(empty_body())?;
+    let (get_res_parts, get_res_body) = http_request(get_req).await?.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        String::from_utf8(read_all_body(get_res_body).await?)?,
+        "[ERROR] The number of receivers should be 2 but 3.\n"
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should reject a sender with n different from the receivers' n")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?n=2", serve.addr))
+        .body(empty_body())?;
+    let _get_res_join_handle = tokio::spawn(http_request(get_req));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(full_body("this is a content"))?;
+    let (send_res_parts, send_res_body) = http_request(send_req).await?.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        String::from_utf8(read_all_body(send_res_body).await?)?,
+        "[ERROR] The number of receivers should be 2 but 1.\n"
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
 #[it("should pass X-Piping and attach Access-Control-Expose-Headers: X-Piping when sending with X-Piping")]
 async fn f() -> anyhow::Result<()> {
     let serve: Serve = serve().await;
@@ -1015,3 +1316,2651 @@ async fn f() -> anyhow::Result<()> {
     serve.shutdown_tx.send(()).expect("shutdown failed");
     Ok(())
 }
+
+#[it("should remove pipes after transfers")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    for i in 0..10 {
+        let uri = format!("http://{}/mypath{i}", serve.addr).parse::<http::Uri>()?;
+        let send_body_str = format!("this is a content {i}");
+
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(uri.clone())
+            .body(empty_body())?;
+        let send_req = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .uri(uri.clone())
+            .body(full_body(send_body_str.clone()))?;
+        // Change the order of the sender and the receiver
+        let (send_res, get_res) = if i % 2 == 0 {
+            let send_res = http_request(send_req).await?;
+            assert_eq!(serve.piping_server.n_pipes(), 1);
+            (send_res, http_request(get_req).await?)
+        } else {
+            let get_res_join_handle = tokio::spawn(http_request(get_req));
+            tokio::time::sleep(time::Duration::from_millis(50)).await;
+            assert_eq!(serve.piping_server.n_pipes(), 1);
+            let send_res = http_request(send_req).await?;
+            (send_res, get_res_join_handle.await??)
+        };
+        let all_bytes: Vec<u8> = read_all_body(get_res.into_body()).await?;
+        assert_eq!(all_bytes, send_body_str.into_bytes());
+        read_all_body(send_res.into_body()).await?;
+    }
+    assert_eq!(serve.piping_server.n_pipes(), 0);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should free the path when a waiting sender is disconnected")]
+async fn f() -> anyhow::Result<()> {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let serve: Serve = serve().await;
+
+    {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n")
+            .await?;
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await?;
+        assert!(
+            String::from_utf8_lossy(&buf[..n]).contains("[INFO] Waiting for 1 receiver(s)...\n")
+        );
+        assert_eq!(serve.piping_server.n_pipes(), 1);
+        // Disconnect the sender while waiting
+    }
+    tokio::time::sleep(time::Duration::from_millis(1500)).await;
+    assert_eq!(serve.piping_server.n_pipes(), 0);
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(full_body(send_body_str))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(empty_body())?;
+    let all_bytes: Vec<u8> = read_all_body(http_request(get_req).await?.into_body()).await?;
+    assert_eq!(all_bytes, send_body_str.to_owned().into_bytes());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should free the path when a waiting receiver is disconnected")]
+async fn f() -> anyhow::Result<()> {
+    use tokio::io::AsyncWriteExt as _;
+
+    let serve: Serve = serve().await;
+
+    {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(b"GET /mypath HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        assert_eq!(serve.piping_server.n_pipes(), 1);
+        // Disconnect the receiver while waiting
+    }
+    tokio::time::sleep(time::Duration::from_millis(1500)).await;
+    assert_eq!(serve.piping_server.n_pipes(), 0);
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(full_body(send_body_str))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(empty_body())?;
+    let all_bytes: Vec<u8> = read_all_body(http_request(get_req).await?.into_body()).await?;
+    assert_eq!(all_bytes, send_body_str.to_owned().into_bytes());
+
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert!(send_res_body_string.starts_with("[INFO] Waiting for 1 receiver(s)...\n"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should time out a sender waiting longer than ?timeout=")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?timeout=1", serve.addr))
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let send_res_body_string = tokio::time::timeout(
+        time::Duration::from_secs(3),
+        read_all_body(send_res.into_body()),
+    )
+    .await??;
+    assert!(String::from_utf8(send_res_body_string)?
+        .ends_with("[ERROR] No receiver connected within 1 second(s).\n"));
+    assert_eq!(serve.piping_server.n_pipes(), 0);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should time out a receiver waiting longer than the server's maximum")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        receiver_wait_timeout: Some(time::Duration::from_secs(1)),
+        ..Default::default()
+    })
+    .await;
+
+    // NOTE: The timeout is capped by the server's maximum
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?timeout=100", serve.addr))
+        .body(empty_body())?;
+    let get_res =
+        tokio::time::timeout(time::Duration::from_secs(3), http_request(get_req)).await??;
+    let (get_res_parts, get_res_body) = get_res.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(
+        String::from_utf8(read_all_body(get_res_body).await?)?,
+        "[ERROR] No sender connected within 1 second(s).\n"
+    );
+    assert_eq!(serve.piping_server.n_pipes(), 0);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should time out a receiver waiting longer than ?timeout= with a unit")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?timeout=500ms", serve.addr))
+        .body(empty_body())?;
+    let get_res =
+        tokio::time::timeout(time::Duration::from_secs(3), http_request(get_req)).await??;
+    let (get_res_parts, get_res_body) = get_res.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(
+        String::from_utf8(read_all_body(get_res_body).await?)?,
+        "[ERROR] No sender connected within 0.5 second(s).\n"
+    );
+    assert_eq!(serve.piping_server.n_pipes(), 0);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should reject invalid timeout")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?timeout=abc", serve.addr))
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?timeout=0", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::BAD_REQUEST);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should abort a transfer stalled longer than the idle transfer timeout")]
+async fn f() -> anyhow::Result<()> {
+    use futures::SinkExt as _;
+
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        idle_transfer_timeout: Some(time::Duration::from_secs(1)),
+        ..Default::default()
+    })
+    .await;
+
+    let (mut send_body_tx, send_body_rx) = futures::channel::mpsc::channel::<
+        Result<http_body::Frame<Bytes>, std::convert::Infallible>,
+    >(1);
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(http_body_util::StreamBody::new(send_body_rx))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+
+    // Send only the first chunk and stall
+    send_body_tx
+        .send(Ok(http_body::Frame::data(Bytes::from("first chunk"))))
+        .await?;
+
+    let (received, result) = tokio::time::timeout(
+        time::Duration::from_secs(3),
+        read_body_until_end(get_res.into_body()),
+    )
+    .await?;
+    assert_eq!(received, b"first chunk");
+    assert!(result.is_err());
+
+    let send_res_body_string = tokio::time::timeout(
+        time::Duration::from_secs(3),
+        read_all_body(send_res.into_body()),
+    )
+    .await??;
+    assert!(String::from_utf8(send_res_body_string)?
+        .ends_with("[ERROR] transfer stalled: no data moved for 1 second(s).\n"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should not send the receipt until a slow receiver finishes receiving")]
+async fn f() -> anyhow::Result<()> {
+    use futures::StreamExt as _;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // NOTE: The channel buffers all the data so that the sender's body ends before the receiver reads it
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        channel_buffer_size: 64,
+        ..PipingServerConfig::default()
+    })
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(empty_body())?;
+    let get_res_join_handle = tokio::spawn(http_request(get_req));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let chunk = Bytes::from(vec![b'a'; 1024 * 1024]);
+    let n_chunks = 48;
+    let sender_body_ended = Arc::new(AtomicBool::new(false));
+    let stream = futures::stream::iter((0..n_chunks).map({
+        let chunk = chunk.clone();
+        move |_| Ok::<_, std::convert::Infallible>(chunk.clone())
+    }))
+    .chain(futures::stream::poll_fn({
+        let sender_body_ended = Arc::clone(&sender_body_ended);
+        move |_| {
+            sender_body_ended.store(true, Ordering::SeqCst);
+            std::task::Poll::Ready(None)
+        }
+    }));
+    let send_join_handle = tokio::spawn({
+        let piping_server = serve.piping_server.clone();
+        async move {
+            piping_server
+                .send("/mypath", http::HeaderMap::new(), stream)
+                .await
+        }
+    });
+
+    let (get_res_parts, get_res_body) = get_res_join_handle.await??.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+    while !sender_body_ended.load(Ordering::SeqCst) {
+        tokio::time::sleep(time::Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(time::Duration::from_millis(500)).await;
+    // The receipt should not be sent because the receiver has not read the data yet
+    assert!(!send_join_handle.is_finished());
+
+    let all_bytes: Vec<u8> = read_all_body(get_res_body).await?;
+    assert_eq!(all_bytes.len(), chunk.len() * n_chunks);
+    send_join_handle.await??;
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should report partial delivery when one of the receivers is disconnected halfway")]
+async fn f() -> anyhow::Result<()> {
+    use http_body_util::BodyExt as _;
+
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath?n=2", serve.addr).parse::<http::Uri>()?;
+    let mut get_res_join_handles = Vec::new();
+    for _ in 0..2 {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(uri.clone())
+            .body(empty_body())?;
+        get_res_join_handles.push(tokio::spawn(http_request(get_req)));
+    }
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let chunk = Bytes::from(vec![b'a'; 1024 * 1024]);
+    let n_chunks = 16;
+    let stream = futures::stream::iter(
+        (0..n_chunks).map(move |_| Ok::<_, std::convert::Infallible>(chunk.clone())),
+    );
+    let send_join_handle = tokio::spawn({
+        let piping_server = serve.piping_server.clone();
+        async move {
+            piping_server
+                .send("/mypath?n=2", http::HeaderMap::new(), stream)
+                .await
+        }
+    });
+
+    let mut get_res_join_handles = get_res_join_handles.into_iter();
+    let mut get_res_body1 = get_res_join_handles.next().unwrap().await??.into_body();
+    let get_res_body2 = get_res_join_handles.next().unwrap().await??.into_body();
+    // Disconnect the first receiver halfway
+    get_res_body1.frame().await.unwrap()?;
+    drop(get_res_body1);
+    let all_bytes: Vec<u8> = read_all_body(get_res_body2).await?;
+    assert_eq!(all_bytes.len(), 1024 * 1024 * n_chunks);
+
+    let err = send_join_handle.await?.unwrap_err();
+    let message_regex = Regex::new(
+        r"^Delivered to 1 of 2 receiver\(s\): \d+ bytes( \(disconnected\))?, \d+ bytes( \(disconnected\))?\.$",
+    )?;
+    assert!(message_regex.is_match(&err.to_string()), "{err}");
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should abort the receiver's response in HTTP/1.1 when the sender is disconnected halfway")]
+async fn f() -> anyhow::Result<()> {
+    use tokio::io::AsyncWriteExt as _;
+
+    let serve: Serve = serve().await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res_fut = tokio::spawn(http_request(get_req));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n")
+            .await?;
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        // Disconnect the sender halfway
+    }
+
+    let get_res = get_res_fut.await??;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    let (received, result) = tokio::time::timeout(
+        time::Duration::from_secs(3),
+        read_body_until_end(get_res.into_body()),
+    )
+    .await?;
+    assert_eq!(received, b"hello");
+    assert!(result.is_err());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should abort the receiver's response in HTTP/2 when the sender is disconnected halfway")]
+async fn f() -> anyhow::Result<()> {
+    use tokio::io::AsyncWriteExt as _;
+
+    let serve: Serve = serve().await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res_fut = tokio::spawn(http2_request(get_req));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n")
+            .await?;
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        // Disconnect the sender halfway
+    }
+
+    let get_res = get_res_fut.await??;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    let (received, result) = tokio::time::timeout(
+        time::Duration::from_secs(3),
+        read_body_until_end(get_res.into_body()),
+    )
+    .await?;
+    assert_eq!(received, b"hello");
+    // NOTE: The stream should be reset by RST_STREAM
+    assert!(format!("{:?}", result.unwrap_err()).contains("Reset("));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should send progress to the sender with ?progress=")]
+async fn f() -> anyhow::Result<()> {
+    use futures::SinkExt as _;
+
+    let serve: Serve = serve().await;
+
+    let (mut send_body_tx, send_body_rx) = futures::channel::mpsc::channel::<
+        Result<http_body::Frame<Bytes>, std::convert::Infallible>,
+    >(1);
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?progress=500ms", serve.addr))
+        .body(http_body_util::StreamBody::new(send_body_rx))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    let get_res_body_fut = tokio::spawn(read_all_body(get_res.into_body()));
+
+    send_body_tx
+        .send(Ok(http_body::Frame::data(Bytes::from("hello"))))
+        .await?;
+    tokio::time::sleep(time::Duration::from_millis(800)).await;
+    drop(send_body_tx);
+    assert_eq!(get_res_body_fut.await??, b"hello");
+
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert!(send_res_body_string.contains("[INFO] 5 B transferred (10 B/s)\n"));
+    assert!(send_res_body_string.contains("[INFO] Sent successfully!"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should reject invalid progress interval")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?progress=abc", serve.addr))
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("X-Piping-Progress", "0s")
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should send events as NDJSON to the sender with ?format=json")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?format=json", serve.addr))
+        .body(full_body(send_body_str))?;
+    let send_res = http_request(send_req).await?;
+    let (send_res_parts, send_res_body) = send_res.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(&send_res_parts.headers, "content-type"),
+        Some("application/x-ndjson")
+    );
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let all_bytes: Vec<u8> = read_all_body(http_request(get_req).await?.into_body()).await?;
+    assert_eq!(all_bytes, send_body_str.as_bytes());
+
+    let send_res_body_string = String::from_utf8(read_all_body(send_res_body).await?)?;
+    let events: Vec<serde_json::Value> = send_res_body_string
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let event_names: Vec<&str> = events
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        event_names,
+        ["waiting", "receiver_connected", "start", "finished"]
+    );
+    assert!(events.iter().all(|event| event["timestamp_ms"].is_u64()));
+    assert_eq!(events[0]["n_receivers"], 1);
+    assert_eq!(events[2]["n_receivers"], 1);
+    assert_eq!(events[3]["n_bytes"], send_body_str.len());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should reject a sender as NDJSON with Accept: application/x-ndjson")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?n=0", serve.addr))
+        .header("Accept", "application/x-ndjson")
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    let (send_res_parts, send_res_body) = send_res.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        get_header_value(&send_res_parts.headers, "content-type"),
+        Some("application/x-ndjson")
+    );
+    let event: serde_json::Value = serde_json::from_slice(&read_all_body(send_res_body).await?)?;
+    assert_eq!(event["event"], "rejected");
+    assert_eq!(event["level"], "error");
+    assert_eq!(event["message"], "n should > 0, but n = 0.");
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should stream the status of a path as Server-Sent Events")]
+async fn f() -> anyhow::Result<()> {
+    use http_body_util::BodyExt as _;
+
+    let serve: Serve = serve().await;
+
+    let status_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/_status/mypath", serve.addr))
+        .body(empty_body())?;
+    let (status_res_parts, mut status_res_body) = http_request(status_req).await?.into_parts();
+    assert_eq!(status_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(&status_res_parts.headers, "content-type"),
+        Some("text/event-stream")
+    );
+
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(full_body(send_body_str))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let all_bytes: Vec<u8> = read_all_body(http_request(get_req).await?.into_body()).await?;
+    assert_eq!(all_bytes, send_body_str.as_bytes());
+
+    let event_regex = Regex::new(r"event: (\w+)\ndata: (.*)\n\n")?;
+    let mut status_string = String::new();
+    tokio::time::timeout(time::Duration::from_secs(3), async {
+        while !status_string.contains("event: finished") {
+            let frame = status_res_body.frame().await.unwrap().unwrap();
+            status_string.push_str(std::str::from_utf8(&frame.into_data().unwrap()).unwrap());
+        }
+    })
+    .await?;
+    let events: Vec<(&str, serde_json::Value)> = event_regex
+        .captures_iter(&status_string)
+        .map(|c| {
+            (
+                c.get(1).unwrap().as_str(),
+                serde_json::from_str(c.get(2).unwrap().as_str()).unwrap(),
+            )
+        })
+        .collect();
+    let event_names: Vec<&str> = events.iter().map(|(name, _)| *name).collect();
+    assert_eq!(
+        event_names,
+        [
+            "state",
+            "sender_waiting",
+            "receiver_waiting",
+            "transfer_started",
+            "finished"
+        ]
+    );
+    assert_eq!(events[0].1["sender_waiting"], false);
+    assert_eq!(events[4].1["n_bytes"], send_body_str.len());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should resume an interrupted upload with Content-Range")]
+async fn f() -> anyhow::Result<()> {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let serve: Serve = serve().await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res_fut = tokio::spawn(http_request(get_req));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let location: String = {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nUpload-Complete: ?1\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello \r\n")
+            .await?;
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await?;
+        let res_string = String::from_utf8_lossy(&buf[..n]).to_string();
+        let location_regex = Regex::new(r"(?i)location: (\S+)\r\n")?;
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        // Disconnect the sender halfway
+        location_regex.captures(&res_string).unwrap()[1].to_owned()
+    };
+    assert!(location.starts_with("/mypath?upload="));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    // Upload offset discovery
+    let head_req = hyper::Request::builder()
+        .method(hyper::Method::HEAD)
+        .uri(format!("http://{}{location}", serve.addr))
+        .body(empty_body())?;
+    let head_res = http_request(head_req).await?;
+    assert_eq!(head_res.status(), http::StatusCode::NO_CONTENT);
+    assert_eq!(
+        get_header_value(head_res.headers(), "upload-offset"),
+        Some("6")
+    );
+
+    // Resume with a wrong offset
+    let resume_req = hyper::Request::builder()
+        .method(hyper::Method::PATCH)
+        .uri(format!("http://{}{location}", serve.addr))
+        .header("Content-Range", "bytes 3-*/*")
+        .body(full_body("lo world"))?;
+    let resume_res = http_request(resume_req).await?;
+    assert_eq!(resume_res.status(), http::StatusCode::CONFLICT);
+    assert_eq!(
+        get_header_value(resume_res.headers(), "upload-offset"),
+        Some("6")
+    );
+
+    let resume_req = hyper::Request::builder()
+        .method(hyper::Method::PATCH)
+        .uri(format!("http://{}{location}", serve.addr))
+        .header("Content-Range", "bytes 6-*/*")
+        .body(full_body("world"))?;
+    let resume_res = http_request(resume_req).await?;
+    assert_eq!(resume_res.status(), http::StatusCode::OK);
+
+    let get_res = get_res_fut.await??;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    let all_bytes: Vec<u8> = read_all_body(get_res.into_body()).await?;
+    assert_eq!(all_bytes, b"hello world");
+
+    let resume_res_body_string = String::from_utf8(read_all_body(resume_res.into_body()).await?)?;
+    assert!(resume_res_body_string.starts_with("[INFO] Resumed the upload at 6 byte(s).\n"));
+    assert!(resume_res_body_string.contains("[INFO] Sent successfully! 11 bytes delivered"));
+
+    // The upload resource is removed after the transfer
+    let head_req = hyper::Request::builder()
+        .method(hyper::Method::HEAD)
+        .uri(format!("http://{}{location}", serve.addr))
+        .body(empty_body())?;
+    let head_res = http_request(head_req).await?;
+    assert_eq!(head_res.status(), http::StatusCode::NOT_FOUND);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should complete an upload with Upload-Complete: ?0 after two interrupted appends")]
+async fn f() -> anyhow::Result<()> {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let serve: Serve = serve().await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res_fut = tokio::spawn(http_request(get_req));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    // The request body does not complete the upload
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Upload-Complete", "?0")
+        .body(full_body("hello "))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+    let location = get_header_value(send_res.headers(), "location")
+        .unwrap()
+        .to_owned();
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert!(
+        send_res_body_string
+            .ends_with("[INFO] Uploaded 6 byte(s). Append the rest to complete the upload.\n"),
+        "{send_res_body_string}"
+    );
+
+    // The first append does not complete the upload either
+    let append_req = hyper::Request::builder()
+        .method(hyper::Method::PATCH)
+        .uri(format!("http://{}{location}", serve.addr))
+        .header("Upload-Offset", "6")
+        .header("Upload-Complete", "?0")
+        .body(full_body("wor"))?;
+    let append_res = http_request(append_req).await?;
+    assert_eq!(append_res.status(), http::StatusCode::OK);
+    let append_res_body_string = String::from_utf8(read_all_body(append_res.into_body()).await?)?;
+    assert!(
+        append_res_body_string
+            .ends_with("[INFO] Uploaded 9 byte(s). Append the rest to complete the upload.\n"),
+        "{append_res_body_string}"
+    );
+
+    // The second append is disconnected halfway
+    {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(format!("PATCH {location} HTTP/1.1\r\nHost: localhost\r\nUpload-Offset: 9\r\nUpload-Complete: ?1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nld\r\n").as_bytes())
+            .await?;
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await?;
+        assert!(String::from_utf8_lossy(&buf[..n]).starts_with("HTTP/1.1 200 OK\r\n"));
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        // Disconnect the sender halfway
+    }
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let head_req = hyper::Request::builder()
+        .method(hyper::Method::HEAD)
+        .uri(format!("http://{}{location}", serve.addr))
+        .body(empty_body())?;
+    let head_res = http_request(head_req).await?;
+    assert_eq!(
+        get_header_value(head_res.headers(), "upload-offset"),
+        Some("11")
+    );
+
+    let append_req = hyper::Request::builder()
+        .method(hyper::Method::PATCH)
+        .uri(format!("http://{}{location}", serve.addr))
+        .header("Upload-Offset", "11")
+        .header("Upload-Complete", "?1")
+        .body(full_body("!"))?;
+    let append_res = http_request(append_req).await?;
+    assert_eq!(append_res.status(), http::StatusCode::OK);
+
+    let get_res = get_res_fut.await??;
+    let all_bytes: Vec<u8> = read_all_body(get_res.into_body()).await?;
+    assert_eq!(all_bytes, b"hello world!");
+    let append_res_body_string = String::from_utf8(read_all_body(append_res.into_body()).await?)?;
+    assert!(append_res_body_string.contains("[INFO] Sent successfully! 12 bytes delivered"));
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should give up an interrupted upload after the grace period")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        upload_resumption_grace_period: time::Duration::from_millis(300),
+        ..Default::default()
+    })
+    .await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res_fut = tokio::spawn(http_request(get_req));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Upload-Complete", "?0")
+        .body(full_body("hello "))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+    let location = get_header_value(send_res.headers(), "location")
+        .unwrap()
+        .to_owned();
+
+    // The receiver's response is aborted without the rest of the upload
+    let get_res = get_res_fut.await??;
+    let (bytes, result) = tokio::time::timeout(
+        time::Duration::from_secs(5),
+        read_body_until_end(get_res.into_body()),
+    )
+    .await?;
+    assert_eq!(bytes, b"hello ");
+    assert!(result.is_err());
+
+    let head_req = hyper::Request::builder()
+        .method(hyper::Method::HEAD)
+        .uri(format!("http://{}{location}", serve.addr))
+        .body(empty_body())?;
+    let head_res = http_request(head_req).await?;
+    assert_eq!(head_res.status(), http::StatusCode::NOT_FOUND);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should reject an invalid Upload-Complete")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Upload-Complete", "true")
+        .body(full_body("hello"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should replay the data to a receiver reconnecting with Range")]
+async fn f() -> anyhow::Result<()> {
+    use futures::SinkExt as _;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        resume_buffer_size: Some(1024 * 1024),
+        ..Default::default()
+    })
+    .await;
+
+    let (mut send_body_tx, send_body_rx) = futures::channel::mpsc::channel::<
+        Result<http_body::Frame<Bytes>, std::convert::Infallible>,
+    >(1);
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Content-Length", 11)
+        .body(http_body_util::StreamBody::new(send_body_rx))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(b"GET /mypath HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        send_body_tx
+            .send(Ok(http_body::Frame::data(Bytes::from("hello "))))
+            .await?;
+        let mut res_bytes = Vec::new();
+        while !res_bytes.ends_with(b"hello ") {
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await?;
+            assert_ne!(n, 0);
+            res_bytes.extend_from_slice(&buf[..n]);
+        }
+        // Disconnect the receiver halfway
+    }
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Range", "bytes=3-")
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        get_header_value(get_res.headers(), "content-range"),
+        Some("bytes 3-10/11")
+    );
+    assert_eq!(
+        get_header_value(get_res.headers(), "content-length"),
+        Some("8")
+    );
+    let get_res_body_fut = tokio::spawn(read_all_body(get_res.into_body()));
+
+    send_body_tx
+        .send(Ok(http_body::Frame::data(Bytes::from("world"))))
+        .await?;
+    drop(send_body_tx);
+    assert_eq!(get_res_body_fut.await??, b"lo world");
+
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert!(send_res_body_string.contains("[INFO] Sent successfully! 11 bytes delivered"));
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should reject a Range beyond the data and give up reconnection after the grace period")]
+async fn f() -> anyhow::Result<()> {
+    use futures::SinkExt as _;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        resume_buffer_size: Some(1024 * 1024),
+        receiver_resumption_grace_period: time::Duration::from_millis(500),
+        ..Default::default()
+    })
+    .await;
+
+    let (mut send_body_tx, send_body_rx) = futures::channel::mpsc::channel::<
+        Result<http_body::Frame<Bytes>, std::convert::Infallible>,
+    >(1);
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Content-Length", 11)
+        .body(http_body_util::StreamBody::new(send_body_rx))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(b"GET /mypath HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        send_body_tx
+            .send(Ok(http_body::Frame::data(Bytes::from("hello "))))
+            .await?;
+        let mut res_bytes = Vec::new();
+        while !res_bytes.ends_with(b"hello ") {
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await?;
+            assert_ne!(n, 0);
+            res_bytes.extend_from_slice(&buf[..n]);
+        }
+        // Disconnect the receiver halfway
+    }
+    // Let the transfer detect the disconnection
+    send_body_tx
+        .send(Ok(http_body::Frame::data(Bytes::from("wor"))))
+        .await?;
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Range", "bytes=11-")
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        get_header_value(get_res.headers(), "content-range"),
+        Some("bytes */11")
+    );
+
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert!(
+        send_res_body_string.ends_with("[INFO] All receiver(s) was/were halfway disconnected.\n"),
+        "{send_res_body_string}"
+    );
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should reject a Range to a transfer without Content-Length")]
+async fn f() -> anyhow::Result<()> {
+    use futures::SinkExt as _;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        resume_buffer_size: Some(1024 * 1024),
+        ..Default::default()
+    })
+    .await;
+
+    let (mut send_body_tx, send_body_rx) = futures::channel::mpsc::channel::<
+        Result<http_body::Frame<Bytes>, std::convert::Infallible>,
+    >(1);
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(http_body_util::StreamBody::new(send_body_rx))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    {
+        let mut stream = tokio::net::TcpStream::connect(serve.addr).await?;
+        stream
+            .write_all(b"GET /mypath HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        send_body_tx
+            .send(Ok(http_body::Frame::data(Bytes::from("hello "))))
+            .await?;
+        let mut res_bytes = Vec::new();
+        while !res_bytes.windows(6).any(|window| window == b"hello ") {
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await?;
+            assert_ne!(n, 0);
+            res_bytes.extend_from_slice(&buf[..n]);
+        }
+        // Disconnect the receiver halfway
+    }
+    // Let the transfer detect the disconnection
+    send_body_tx
+        .send(Ok(http_body::Frame::data(Bytes::from("wor"))))
+        .await?;
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Range", "bytes=6-")
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(get_header_value(get_res.headers(), "content-range"), None);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"[ERROR] The transfer without Content-Length cannot be resumed on '/mypath'.\n"
+    );
+
+    drop(send_body_tx);
+    drop(send_res);
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should store data and hand it to the first receiver with ?store=")]
+async fn f() -> anyhow::Result<()> {
+    let spool_dir = create_temp_dir();
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        spool_dir: Some(spool_dir.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?store=1h", serve.addr))
+        .header("Content-Type", "text/plain")
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert_eq!(
+        send_res_body_string,
+        "[INFO] Stored 17 bytes for 3600 second(s) until a receiver connects.\n"
+    );
+
+    // Another sender cannot store data on the same path
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?store=1h", serve.addr))
+        .body(full_body("another content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(get_res.headers(), "content-type"),
+        Some("text/plain")
+    );
+    assert_eq!(
+        get_header_value(get_res.headers(), "content-length"),
+        Some("17")
+    );
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+
+    // The stored data is removed after received
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?timeout=1", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::REQUEST_TIMEOUT);
+    let file_names: Vec<_> = std::fs::read_dir(&spool_dir)?
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(file_names, vec!["index.json"]);
+
+    serve.shutdown().await?;
+    std::fs::remove_dir_all(spool_dir)?;
+    Ok(())
+}
+
+#[it("should keep stored data across restarts")]
+async fn f() -> anyhow::Result<()> {
+    let spool_dir = create_temp_dir();
+    let config = PipingServerConfig {
+        spool_dir: Some(spool_dir.clone()),
+        ..Default::default()
+    };
+
+    let serve: Serve = serve_with_config(config.clone()).await;
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?store=1h", serve.addr))
+        .header("X-Piping", "mymetadata")
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+    serve.shutdown().await?;
+
+    let serve: Serve = serve_with_config(config).await;
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(get_res.headers(), "x-piping"),
+        Some("mymetadata")
+    );
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+
+    serve.shutdown().await?;
+    std::fs::remove_dir_all(spool_dir)?;
+    Ok(())
+}
+
+#[it("should reject data to store exceeding the limit")]
+async fn f() -> anyhow::Result<()> {
+    let spool_dir = create_temp_dir();
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        spool_dir: Some(spool_dir.clone()),
+        spool_max_file_size: Some(10),
+        ..Default::default()
+    })
+    .await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?store=1h", serve.addr))
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert_eq!(
+        send_res_body_string,
+        "[ERROR] The data exceeds the limit of 10 bytes.\n"
+    );
+
+    serve.shutdown().await?;
+    std::fs::remove_dir_all(spool_dir)?;
+    Ok(())
+}
+
+#[it("should store data on a path after an upload to store on the path is aborted")]
+async fn f() -> anyhow::Result<()> {
+    use futures::StreamExt as _;
+
+    let spool_dir = create_temp_dir();
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        spool_dir: Some(spool_dir.clone()),
+        spool_max_total_size: Some(10),
+        ..Default::default()
+    })
+    .await;
+
+    let send_body_stream = futures::stream::iter([Ok::<_, std::convert::Infallible>(
+        http_body::Frame::data(Bytes::from("hello ")),
+    )])
+    .chain(futures::stream::pending());
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri("/mypath?store=1h")
+        .body(http_body_util::StreamBody::new(send_body_stream))?;
+    let send_res_fut = serve.piping_server.clone().handle_request(send_req);
+    // Abort the upload halfway by dropping the request handling
+    assert!(
+        tokio::time::timeout(time::Duration::from_millis(200), send_res_fut)
+            .await
+            .is_err()
+    );
+
+    // NOTE: The total size would exceed the limit if the aborted data were counted
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?store=1h", serve.addr))
+        .body(full_body("my data"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert_eq!(
+        send_res_body_string,
+        "[INFO] Stored 7 bytes for 3600 second(s) until a receiver connects.\n"
+    );
+    // The partial data of the aborted upload is removed
+    let n_data_files = std::fs::read_dir(&spool_dir)?
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("data".as_ref()))
+        .count();
+    assert_eq!(n_data_files, 1);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(read_all_body(get_res.into_body()).await?, b"my data");
+
+    serve.shutdown().await?;
+    std::fs::remove_dir_all(spool_dir)?;
+    Ok(())
+}
+
+#[it("should send data to store to a receiver waiting already")]
+async fn f() -> anyhow::Result<()> {
+    let spool_dir = create_temp_dir();
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        spool_dir: Some(spool_dir.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res_fut = tokio::spawn(http_request(get_req));
+    // Wait for the receiver to connect
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?store=1h", serve.addr))
+        .body(full_body("hello world"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_res = tokio::time::timeout(time::Duration::from_secs(5), get_res_fut).await???;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(read_all_body(get_res.into_body()).await?, b"hello world");
+    let send_res_body_string = String::from_utf8(read_all_body(send_res.into_body()).await?)?;
+    assert!(
+        send_res_body_string.contains("[INFO] Sent successfully!"),
+        "{send_res_body_string}"
+    );
+    // Nothing is stored
+    assert_eq!(
+        std::fs::read_dir(&spool_dir)?
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("data".as_ref()))
+            .count(),
+        0
+    );
+
+    serve.shutdown().await?;
+    std::fs::remove_dir_all(spool_dir)?;
+    Ok(())
+}
+
+#[it("should use the configured CORS origin, reserved paths and pages")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        cors_allow_origin: Some(http::HeaderValue::from_static("https://example.com")),
+        additional_reserved_paths: vec!["/myreserved".to_owned()],
+        index_html: Some("<h1>My Piping Server</h1>".to_owned()),
+        help_text: Some("curl -T myfile {base_url}mypath\n".to_owned()),
+        ..Default::default()
+    })
+    .await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(
+        get_header_value(get_res.headers(), "access-control-allow-origin"),
+        Some("https://example.com")
+    );
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"<h1>My Piping Server</h1>"
+    );
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/help", serve.addr))
+        .header("Host", "example.com")
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"curl -T myfile http://example.com/mypath\n"
+    );
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/myreserved", serve.addr))
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/myreserved", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::NOT_FOUND);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should not send CORS headers if CORS is disabled")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        cors_allow_origin: None,
+        ..Default::default()
+    })
+    .await;
+
+    let req = hyper::Request::builder()
+        .method(hyper::Method::OPTIONS)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let res = http_request(req).await?;
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert!(!res
+        .headers()
+        .keys()
+        .any(|name| name.as_str().starts_with("access-control-")));
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should attach Vary: Origin with the configured CORS origin except *")]
+async fn f() -> anyhow::Result<()> {
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        cors_allow_origin: Some(http::HeaderValue::from_static("https://example.com")),
+        ..Default::default()
+    })
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    for headers in [send_res.headers(), get_res.headers()] {
+        assert_eq!(
+            get_header_value(headers, "access-control-allow-origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(get_header_value(headers, "vary"), Some("Origin"));
+    }
+    serve.shutdown().await?;
+
+    let serve: Serve = serve_with_config(PipingServerConfig::default()).await;
+    let req = hyper::Request::builder()
+        .method(hyper::Method::OPTIONS)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let res = http_request(req).await?;
+    assert_eq!(
+        get_header_value(res.headers(), "access-control-allow-origin"),
+        Some("*")
+    );
+    assert_eq!(get_header_value(res.headers(), "vary"), None);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(feature = "tower")]
+#[it("should handle a transfer as tower::Service with any body type")]
+async fn f() -> anyhow::Result<()> {
+    use tower_service::Service as _;
+
+    let mut piping_server = PipingServer::new();
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri("/mypath")
+        .body(full_body("this is a content"))?;
+    let send_res = piping_server.call(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+    let send_res_body_fut = tokio::spawn(http_body_util::BodyExt::collect(send_res.into_body()));
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri("/mypath")
+        .body(empty_body())?;
+    let get_res = piping_server.call(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    let body = http_body_util::BodyExt::collect(get_res.into_body())
+        .await?
+        .to_bytes();
+    assert_eq!(body, "this is a content");
+    let send_res_body = send_res_body_fut.await??.to_bytes();
+    assert!(String::from_utf8(send_res_body.to_vec())?.contains("[INFO] Sent successfully!"));
+
+    // HTTPS is specified by the request extension
+    let mut get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri("/help")
+        .header("Host", "example.com")
+        .body(empty_body())?;
+    get_req
+        .extensions_mut()
+        .insert(piping_server::piping_server::UsesHttps(true));
+    let get_res = piping_server.call(get_req).await?;
+    let body = http_body_util::BodyExt::collect(get_res.into_body())
+        .await?
+        .to_bytes();
+    assert!(String::from_utf8(body.to_vec())?.contains("curl https://example.com/mypath"));
+    Ok(())
+}
+
+#[it("should send data in process to a receiver over HTTP")]
+async fn f() -> anyhow::Result<()> {
+    let serve = serve().await;
+
+    let mut headers = http::HeaderMap::new();
+    headers.insert("Content-Type", "text/plain".parse()?);
+    let send_fut = tokio::spawn({
+        let piping_server = serve.piping_server.clone();
+        async move {
+            use futures::StreamExt as _;
+            let stream = futures::stream::iter(["this is ", "a content"])
+                .map(|chunk| Ok::<_, std::convert::Infallible>(Bytes::from(chunk)));
+            piping_server.send("/mypath", headers, stream).await
+        }
+    });
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(get_res.headers(), "content-type"),
+        Some("text/plain")
+    );
+    let body = read_all_body(get_res.into_body()).await?;
+    assert_eq!(body, b"this is a content");
+    send_fut.await??;
+
+    // A rejected sender gets the reason
+    let err = serve
+        .piping_server
+        .send(
+            "/mypath?n=0",
+            http::HeaderMap::new(),
+            futures::stream::empty::<Result<Bytes, std::convert::Infallible>>(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "n should > 0, but n = 0.");
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should receive data in process from a sender over HTTP")]
+async fn f() -> anyhow::Result<()> {
+    use futures::TryStreamExt as _;
+
+    let serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Content-Type", "text/plain")
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(http_request(send_req));
+
+    let (headers, stream) = serve.piping_server.receive("/mypath").await?;
+    assert_eq!(
+        get_header_value(&headers, "content-type"),
+        Some("text/plain")
+    );
+    let body: Vec<Bytes> = stream.try_collect().await?;
+    assert_eq!(body.concat(), b"this is a content");
+    let send_res = send_res_fut.await??;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+    let send_res_body = read_all_body(send_res.into_body()).await?;
+    assert!(String::from_utf8(send_res_body)?.contains("[INFO] Sent successfully!"));
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should notify the event listener of the lifecycle of a transfer")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::event_listener::{
+        PipingEventListener, RejectionEvent, TransferEndEvent, TransferStartEvent, WaitingEvent,
+    };
+
+    #[derive(Clone, Default)]
+    struct RecordingListener {
+        events: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl PipingEventListener for RecordingListener {
+        fn on_sender_waiting(&self, event: &WaitingEvent) {
+            let mut events = self.events.lock().unwrap();
+            events.push(format!("sender_waiting {}", event.path));
+        }
+        fn on_receiver_waiting(&self, event: &WaitingEvent) {
+            assert!(event.remote_addr.unwrap().ip().is_loopback());
+            let mut events = self.events.lock().unwrap();
+            events.push(format!("receiver_waiting {}", event.path));
+        }
+        fn on_transfer_start(&self, event: &TransferStartEvent) {
+            assert!(event.sender_addr.unwrap().ip().is_loopback());
+            let mut events = self.events.lock().unwrap();
+            events.push(format!(
+                "start {} {:?} {}",
+                event.path,
+                event.headers.get("content-type").unwrap(),
+                event.receiver_addrs.len()
+            ));
+        }
+        fn on_transfer_completed(&self, event: &TransferEndEvent) {
+            let mut events = self.events.lock().unwrap();
+            events.push(format!("completed {} {}", event.path, event.n_bytes));
+        }
+        fn on_transfer_aborted(&self, event: &TransferEndEvent) {
+            let mut events = self.events.lock().unwrap();
+            events.push(format!("aborted {} {}", event.path, event.n_bytes));
+        }
+        fn on_rejected(&self, event: &RejectionEvent) {
+            let mut events = self.events.lock().unwrap();
+            events.push(format!(
+                "rejected {} {} {} {}",
+                event.method, event.path, event.status, event.reason
+            ));
+        }
+    }
+
+    let listener = RecordingListener::default();
+    let serve =
+        serve_piping_server(PipingServer::new().with_event_listener(listener.clone())).await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res_fut = tokio::spawn(http_request(get_req));
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Content-Type", "text/plain")
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    let get_res = get_res_fut.await??;
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+    read_all_body(send_res.into_body()).await?;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?n=0", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::BAD_REQUEST);
+
+    assert_eq!(
+        *listener.events.lock().unwrap(),
+        vec![
+            "receiver_waiting /mypath",
+            "start /mypath \"text/plain\" 1",
+            "completed /mypath 17",
+            "rejected GET /mypath 400 Bad Request n should > 0, but n = 0.",
+        ]
+    );
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should require a bearer token except for the built-in pages")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::BearerTokenAuthorizer;
+
+    let serve = serve_piping_server(
+        PipingServer::new().with_authorizer(BearerTokenAuthorizer::new(["mytoken".to_owned()])),
+    )
+    .await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Authorization", "Bearer wrongtoken")
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        get_header_value(send_res.headers(), "www-authenticate"),
+        Some("Bearer")
+    );
+    assert_eq!(
+        read_all_body(send_res.into_body()).await?,
+        b"[ERROR] Unauthorized.\n"
+    );
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/help", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Authorization", "Bearer mytoken")
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(http_request(send_req));
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Authorization", "Bearer mytoken")
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+    assert_eq!(send_res_fut.await??.status(), http::StatusCode::OK);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should require HTTP Basic authentication against an htpasswd file")]
+async fn f() -> anyhow::Result<()> {
+    use base64::Engine as _;
+    use piping_server::auth::HtpasswdAuthorizer;
+
+    let dir = create_temp_dir();
+    let htpasswd_path = dir.join("htpasswd");
+    // NOTE: The passwords are "password" and "secret"
+    std::fs::write(
+        &htpasswd_path,
+        "alice:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\nbob:secret\n",
+    )?;
+    let serve = serve_piping_server(
+        PipingServer::new().with_authorizer(HtpasswdAuthorizer::from_file(&htpasswd_path)?),
+    )
+    .await;
+    let basic = |credentials: &str| {
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        )
+    };
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Authorization", basic("alice:wrongpassword"))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::UNAUTHORIZED);
+    assert!(get_header_value(get_res.headers(), "www-authenticate")
+        .unwrap()
+        .starts_with("Basic "));
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Authorization", basic("bob:secret"))
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(http_request(send_req));
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("Authorization", basic("alice:password"))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+    assert_eq!(send_res_fut.await??.status(), http::StatusCode::OK);
+
+    serve.shutdown().await?;
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[it("should pair a sender and receivers on the path rewritten by the authorizer")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::{Authorization, AuthorizationRequest, Authorizer};
+
+    // Namespace paths by X-User header
+    struct UserAuthorizer;
+
+    impl Authorizer for UserAuthorizer {
+        fn authorize(&self, req: &AuthorizationRequest) -> Authorization {
+            match req
+                .headers
+                .get("x-user")
+                .and_then(|user| user.to_str().ok())
+            {
+                Some(user) => Authorization::RewritePath(format!("/{user}{}", req.path)),
+                None => Authorization::Deny {
+                    status: http::StatusCode::FORBIDDEN,
+                    message: "X-User is required.".to_owned(),
+                },
+            }
+        }
+    }
+
+    let serve = serve_piping_server(PipingServer::new().with_authorizer(UserAuthorizer)).await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::FORBIDDEN);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"[ERROR] X-User is required.\n"
+    );
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("X-User", "alice")
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(http_request(send_req));
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("X-User", "alice")
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+    assert_eq!(send_res_fut.await??.status(), http::StatusCode::OK);
+
+    // Senders of different users do not conflict on the same path
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("X-User", "alice")
+        .body(full_body("this is a content"))?;
+    let send_res1 = http_request(send_req).await?;
+    assert_eq!(send_res1.status(), http::StatusCode::OK);
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .header("X-User", "bob")
+        .body(full_body("this is a content"))?;
+    let send_res2 = http_request(send_req).await?;
+    assert_eq!(send_res2.status(), http::StatusCode::OK);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should verify bcrypt and MD5 passwords in an htpasswd file")]
+async fn f() -> anyhow::Result<()> {
+    use base64::Engine as _;
+    use piping_server::auth::HtpasswdAuthorizer;
+
+    // NOTE: The passwords are "password" and "mypassword"
+    let authorizer = HtpasswdAuthorizer::parse(
+        "alice:$2y$04$UuTkLRZZ6QofpDOlMz32MuuxEHA43WOemOYHPz6.SjsVsyO1tDU96\nbob:$apr1$abcdefgh$7BgPNa9e5BDegjQKI8xWp0\n",
+    )?;
+    let serve = serve_piping_server(PipingServer::new().with_authorizer(authorizer)).await;
+
+    // NOTE: The passwords verified already are checked again
+    for (i, (credentials, status)) in [
+        ("alice:password", http::StatusCode::OK),
+        ("alice:mypassword", http::StatusCode::UNAUTHORIZED),
+        ("bob:mypassword", http::StatusCode::OK),
+        ("bob:password", http::StatusCode::UNAUTHORIZED),
+        ("alice:password", http::StatusCode::OK),
+        ("alice:mypassword", http::StatusCode::UNAUTHORIZED),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let send_req = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .uri(format!("http://{}/mypath{i}", serve.addr))
+            .header(
+                "Authorization",
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(credentials)
+                ),
+            )
+            .body(full_body("this is a content"))?;
+        let send_res = http_request(send_req).await?;
+        assert_eq!(send_res.status(), status, "{credentials}");
+    }
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should reject crypt() password hashes in an htpasswd file")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::HtpasswdAuthorizer;
+
+    // NOTE: crypt() hash of "password"
+    let err = HtpasswdAuthorizer::parse("alice:rqVTKtRlPS2tM\n")
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "unsupported crypt() password hash of 'alice' in htpasswd line 1 (use `htpasswd -B`)"
+    );
+    // Plain text passwords of other lengths are accepted
+    HtpasswdAuthorizer::parse("alice:mypassword\n")?;
+    Ok(())
+}
+
+#[it("should allow tokens to send or receive only under their path prefixes")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::ScopedTokenAuthorizer;
+
+    let authorizer = ScopedTokenAuthorizer::parse(
+        r#"{
+            "tokens": [
+                {"token": "citoken", "roles": ["send"], "path_prefix": "/ci/"},
+                {"token": "deploytoken", "roles": ["receive"], "path_prefix": "/ci/"}
+            ]
+        }"#,
+    )?;
+    let serve = serve_piping_server(PipingServer::new().with_authorizer(authorizer)).await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/ci/artifact", serve.addr))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::UNAUTHORIZED);
+
+    // The sender's token cannot receive
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/ci/artifact", serve.addr))
+        .header("Authorization", "Bearer citoken")
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::FORBIDDEN);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"[ERROR] The token is not allowed to receive from '/ci/artifact'.\n"
+    );
+
+    // The token cannot send out of the path prefix
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/cixyz", serve.addr))
+        .header("Authorization", "Bearer citoken")
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::FORBIDDEN);
+    assert_eq!(
+        read_all_body(send_res.into_body()).await?,
+        b"[ERROR] The token is not allowed to send to '/cixyz'.\n"
+    );
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/ci/artifact", serve.addr))
+        .header("Authorization", "Bearer citoken")
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(http_request(send_req));
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!(
+            "http://{}/ci/artifact?token=deploytoken",
+            serve.addr
+        ))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+    assert_eq!(send_res_fut.await??.status(), http::StatusCode::OK);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should not allow a token to receive out of its scope with ?upload=")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::ScopedTokenAuthorizer;
+
+    let authorizer = ScopedTokenAuthorizer::parse(
+        r#"{
+            "tokens": [
+                {"token": "citoken", "roles": ["send"], "path_prefix": "/ci/"},
+                {"token": "othertoken", "roles": ["send", "receive"], "path_prefix": "/other/"}
+            ]
+        }"#,
+    )?;
+    let serve = serve_piping_server(PipingServer::new().with_authorizer(authorizer)).await;
+
+    for (path_and_query, token, message) in [
+        (
+            "/ci/artifact?upload=x",
+            "citoken",
+            "The token is not allowed to receive from '/ci/artifact'.",
+        ),
+        (
+            "/ci/artifact?upload=x",
+            "othertoken",
+            "The token is not allowed to receive from '/ci/artifact'.",
+        ),
+    ] {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(format!("http://{}{path_and_query}", serve.addr))
+            .header("Authorization", format!("Bearer {token}"))
+            .body(empty_body())?;
+        let get_res = http_request(get_req).await?;
+        assert_eq!(get_res.status(), http::StatusCode::FORBIDDEN);
+        assert_eq!(
+            read_all_body(get_res.into_body()).await?,
+            format!("[ERROR] {message}\n").as_bytes()
+        );
+    }
+
+    // A method not to a pipe is denied
+    let delete_req = hyper::Request::builder()
+        .method(hyper::Method::DELETE)
+        .uri(format!("http://{}/other/path", serve.addr))
+        .header("Authorization", "Bearer othertoken")
+        .body(empty_body())?;
+    let delete_res = http_request(delete_req).await?;
+    assert_eq!(delete_res.status(), http::StatusCode::FORBIDDEN);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should not leak credentials in query parameters to the access log")]
+async fn f() -> anyhow::Result<()> {
+    let dir = create_temp_dir();
+    let log_path = dir.join("piping-server.log");
+    let http_port = available_port();
+    let server_process = run_server_process_with_stderr(
+        &["--http-port".to_owned(), http_port.to_string()],
+        http_port,
+        std::fs::File::create(&log_path)?.into(),
+    )
+    .await?;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!(
+            "http://127.0.0.1:{http_port}/mypath?token=mytoken&n=1&sig=mysig"
+        ))
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+    drop(server_process);
+
+    let log = std::fs::read_to_string(&log_path)?;
+    assert!(
+        log.contains("POST /mypath?token=REDACTED&n=1&sig=REDACTED HTTP/1.1"),
+        "{log}"
+    );
+    assert!(!log.contains("mytoken") && !log.contains("mysig"), "{log}");
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[it("should not pass Authorization header of the sender to the event listener")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::BearerTokenAuthorizer;
+    use piping_server::event_listener::{PipingEventListener, TransferStartEvent};
+
+    #[derive(Clone, Default)]
+    struct HeadersListener {
+        headers: std::sync::Arc<std::sync::Mutex<Option<http::HeaderMap>>>,
+    }
+
+    impl PipingEventListener for HeadersListener {
+        fn on_transfer_start(&self, event: &TransferStartEvent) {
+            *self.headers.lock().unwrap() = Some(event.headers.clone());
+        }
+    }
+
+    let listener = HeadersListener::default();
+    let serve = serve_piping_server(
+        PipingServer::new()
+            .with_authorizer(BearerTokenAuthorizer::new(["mytoken".to_owned()]))
+            .with_event_listener(listener.clone()),
+    )
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .header("Authorization", "Bearer mytoken")
+        .header("Content-Type", "text/plain")
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(http_request(send_req));
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri)
+        .header("Authorization", "Bearer mytoken")
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+    assert_eq!(send_res_fut.await??.status(), http::StatusCode::OK);
+
+    let headers = listener.headers.lock().unwrap().take().unwrap();
+    assert_eq!(
+        get_header_value(&headers, "content-type"),
+        Some("text/plain")
+    );
+    assert_eq!(get_header_value(&headers, "authorization"), None);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should require URLs signed with the key of the role")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::{sign_path, Role, UrlSigningAuthorizer};
+
+    let key = b"mykey";
+    let serve = serve_piping_server(
+        PipingServer::new().with_authorizer(UrlSigningAuthorizer::new(None, Some(key))),
+    )
+    .await;
+    let expires = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs()
+        + 60;
+
+    for (path_and_query, message) in [
+        ("/mypath".to_owned(), "A signed URL is required."),
+        (
+            sign_path(key, Role::Receive, "/mypath", 1),
+            "The signed URL has expired.",
+        ),
+        (
+            sign_path(key, Role::Receive, "/otherpath", expires).replace("/otherpath", "/mypath"),
+            "Invalid signature.",
+        ),
+        (
+            sign_path(key, Role::Send, "/mypath", expires),
+            "Invalid signature.",
+        ),
+        (
+            sign_path(b"wrongkey", Role::Receive, "/mypath", expires),
+            "Invalid signature.",
+        ),
+    ] {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(format!("http://{}{path_and_query}", serve.addr))
+            .body(empty_body())?;
+        let get_res = http_request(get_req).await?;
+        assert_eq!(get_res.status(), http::StatusCode::FORBIDDEN);
+        assert_eq!(
+            read_all_body(get_res.into_body()).await?,
+            format!("[ERROR] {message}\n").as_bytes()
+        );
+    }
+
+    // The sender is not required to sign because no key is specified for senders
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr))
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(http_request(send_req));
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!(
+            "http://{}{}",
+            serve.addr,
+            sign_path(key, Role::Receive, "/mypath", expires)
+        ))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        b"this is a content"
+    );
+    assert_eq!(send_res_fut.await??.status(), http::StatusCode::OK);
+
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should require a signed URL to receive with ?upload=")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::UrlSigningAuthorizer;
+
+    let serve = serve_piping_server(
+        PipingServer::new().with_authorizer(UrlSigningAuthorizer::new(None, Some(b"mykey"))),
+    )
+    .await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/secret", serve.addr))
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(http_request(send_req));
+
+    for (method, path_and_query) in [
+        (hyper::Method::GET, "/secret?upload=x"),
+        (hyper::Method::DELETE, "/secret"),
+    ] {
+        let req = hyper::Request::builder()
+            .method(method)
+            .uri(format!("http://{}{path_and_query}", serve.addr))
+            .body(empty_body())?;
+        let res = http_request(req).await?;
+        assert_eq!(res.status(), http::StatusCode::FORBIDDEN);
+        assert_eq!(
+            read_all_body(res.into_body()).await?,
+            b"[ERROR] A signed URL is required.\n"
+        );
+    }
+
+    send_res_fut.abort();
+    serve.shutdown().await?;
+    Ok(())
+}
+
+#[it("should pass the identity in the verified client certificate to the authorizer")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::auth::{Authorization, AuthorizationRequest, Authorizer};
+    use piping_server::util::{load_tls_config_with_client_auth, ClientAuth};
+
+    // Namespace paths by the common name of the client certificate
+    struct ClientIdentityAuthorizer;
+
+    impl Authorizer for ClientIdentityAuthorizer {
+        fn authorize(&self, req: &AuthorizationRequest) -> Authorization {
+            match req.client_identity {
+                Some(client_identity) => {
+                    assert_eq!(client_identity.dns_names, vec!["alice.example.com"]);
+                    let common_name = client_identity.common_name.as_ref().unwrap();
+                    Authorization::RewritePath(format!("/{common_name}{}", req.path))
+                }
+                None => Authorization::Deny {
+                    status: http::StatusCode::FORBIDDEN,
+                    message: "A client certificate is required.".to_owned(),
+                },
+            }
+        }
+    }
+
+    let piping_server = PipingServer::new().with_authorizer(ClientIdentityAuthorizer);
+    let tls_config = load_tls_config_with_client_auth(
+        fixture_path("server.crt"),
+        fixture_path("server.key").display().to_string(),
+        Some(&ClientAuth {
+            ca_path: fixture_path("ca.crt"),
+            required: false,
+        }),
+    )?;
+    let addr = serve_https(piping_server.clone(), tls_config).await?;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("https://localhost:{}/mypath", addr.port()))
+        .body(full_body("this is a content"))?;
+    let send_res = https_request(send_req, None).await?;
+    assert_eq!(send_res.status(), http::StatusCode::FORBIDDEN);
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("https://localhost:{}/mypath", addr.port()))
+        .body(full_body("this is a content"))?;
+    let send_res_fut = tokio::spawn(https_request(send_req, Some("client")));
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("https://localhost:{}/mypath", addr.port()))
+        .body(empty_body())?;
+    let get_res = https_request(get_req, Some("client")).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await?,
+        "this is a content".as_bytes()
+    );
+    assert_eq!(send_res_fut.await??.status(), http::StatusCode::OK);
+
+    // A client without a certificate is rejected in the TLS handshake if required
+    let tls_config = load_tls_config_with_client_auth(
+        fixture_path("server.crt"),
+        fixture_path("server.key").display().to_string(),
+        Some(&ClientAuth {
+            ca_path: fixture_path("ca.crt"),
+            required: true,
+        }),
+    )?;
+    let addr = serve_https(PipingServer::new(), tls_config).await?;
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("https://localhost:{}/version", addr.port()))
+        .body(empty_body())?;
+    assert!(https_request(get_req, None).await.is_err());
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("https://localhost:{}/version", addr.port()))
+        .body(empty_body())?;
+    assert_eq!(
+        https_request(get_req, Some("client")).await?.status(),
+        http::StatusCode::OK
+    );
+    Ok(())
+}
+
+#[it("should select the certificate by SNI falling back to the default certificate")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::util::{host_certs_in_dir, hot_reload_sni_tls_cfg, HostCert};
+
+    let get_version_status = |host: &'static str, port: u16| async move {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(format!("https://{host}:{port}/version"))
+            .body(empty_body())?;
+        anyhow::Ok(https_request(get_req, None).await?.status())
+    };
+
+    let tls_cert_dir = create_temp_dir();
+    std::fs::copy(
+        fixture_path("example.com.crt"),
+        tls_cert_dir.join("example.com.crt"),
+    )?;
+    std::fs::copy(
+        fixture_path("example.com.key"),
+        tls_cert_dir.join("example.com.key"),
+    )?;
+    let mut host_certs = host_certs_in_dir(&tls_cert_dir)?;
+    host_certs.push(
+        format!(
+            "localhost={},{}",
+            fixture_path("server.crt").display(),
+            fixture_path("server.key").display()
+        )
+        .parse::<HostCert>()?,
+    );
+    let tls_cfg_rwlock =
+        hot_reload_sni_tls_cfg(tokio::runtime::Handle::current(), None, host_certs, None)?;
+    let tls_config = tls_cfg_rwlock.read().await.as_ref().clone();
+    let addr = serve_https(PipingServer::new(), tls_config).await?;
+    assert_eq!(
+        get_version_status("example.com", addr.port()).await?,
+        http::StatusCode::OK
+    );
+    assert_eq!(
+        get_version_status("localhost", addr.port()).await?,
+        http::StatusCode::OK
+    );
+    // No certificate is selected without the default certificate
+    assert!(get_version_status("unknown.example.com", addr.port())
+        .await
+        .is_err());
+
+    let tls_cfg_rwlock = hot_reload_sni_tls_cfg(
+        tokio::runtime::Handle::current(),
+        Some((
+            tls_cert_dir.join("example.com.crt"),
+            tls_cert_dir.join("example.com.key"),
+        )),
+        vec![HostCert {
+            host: "localhost".to_owned(),
+            cert_path: fixture_path("server.crt"),
+            key_path: fixture_path("server.key"),
+        }],
+        None,
+    )?;
+    let tls_config = tls_cfg_rwlock.read().await.as_ref().clone();
+    let addr = serve_https(PipingServer::new(), tls_config).await?;
+    assert_eq!(
+        get_version_status("example.com", addr.port()).await?,
+        http::StatusCode::OK
+    );
+    assert_eq!(
+        get_version_status("localhost", addr.port()).await?,
+        http::StatusCode::OK
+    );
+    Ok(())
+}
+
+#[it("should reload the CA certificates given by a relative path with SNI")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::util::{hot_reload_sni_tls_cfg, ClientAuth};
+
+    let tls_dir = create_temp_dir();
+    // Not a CA of the client certificate at first
+    std::fs::copy(fixture_path("server.crt"), tls_dir.join("ca.crt"))?;
+    let relative_ca_path: std::path::PathBuf = std::env::current_dir()?
+        .components()
+        .skip(1)
+        .map(|_| "..")
+        .collect::<std::path::PathBuf>()
+        .join(tls_dir.join("ca.crt").strip_prefix("/")?);
+    let tls_cfg_rwlock = hot_reload_sni_tls_cfg(
+        tokio::runtime::Handle::current(),
+        Some((fixture_path("server.crt"), fixture_path("server.key"))),
+        vec![],
+        Some(ClientAuth {
+            ca_path: relative_ca_path,
+            required: true,
+        }),
+    )?;
+    let old_tls_config = tls_cfg_rwlock.read().await.clone();
+
+    let mut tls_config = old_tls_config.clone();
+    for _ in 0..100 {
+        // NOTE: Copied repeatedly because the watcher may not have started yet
+        std::fs::copy(fixture_path("ca.crt"), tls_dir.join("ca.crt"))?;
+        tls_config = tls_cfg_rwlock.read().await.clone();
+        if !std::sync::Arc::ptr_eq(&tls_config, &old_tls_config) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(!std::sync::Arc::ptr_eq(&tls_config, &old_tls_config));
+
+    let addr = serve_https(PipingServer::new(), tls_config.as_ref().clone()).await?;
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("https://localhost:{}/version", addr.port()))
+        .body(empty_body())?;
+    assert_eq!(
+        https_request(get_req, Some("client")).await?.status(),
+        http::StatusCode::OK
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+#[it("should reload the certificate when its symbolic link is replaced with SNI")]
+async fn f() -> anyhow::Result<()> {
+    use piping_server::util::hot_reload_sni_tls_cfg;
+
+    // Replace the symbolic link atomically as certbot does on renewal
+    let replace_symlink = |target: std::path::PathBuf, link: std::path::PathBuf| {
+        let tmp_link = link.with_extension("tmp");
+        std::os::unix::fs::symlink(target, &tmp_link)?;
+        std::fs::rename(tmp_link, link)
+    };
+
+    // The certificate for another host name at first
+    let tls_dir = create_temp_dir();
+    let live_dir = tls_dir.join("live");
+    std::fs::create_dir(&live_dir)?;
+    replace_symlink(fixture_path("example.com.crt"), live_dir.join("cert.pem"))?;
+    replace_symlink(fixture_path("example.com.key"), live_dir.join("key.pem"))?;
+    let tls_cfg_rwlock = hot_reload_sni_tls_cfg(
+        tokio::runtime::Handle::current(),
+        Some((live_dir.join("cert.pem"), live_dir.join("key.pem"))),
+        vec![],
+        None,
+    )?;
+    let tls_config = tls_cfg_rwlock.read().await.as_ref().clone();
+    let addr = serve_https(PipingServer::new(), tls_config).await?;
+    let get_version = || async {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(format!("https://localhost:{}/version", addr.port()))
+            .body(empty_body())?;
+        https_request(get_req, None).await
+    };
+    assert!(get_version().await.is_err());
+
+    let mut get_res = None;
+    for _ in 0..100 {
+        // NOTE: Replaced repeatedly because the watcher may not have started yet
+        replace_symlink(fixture_path("server.crt"), live_dir.join("cert.pem"))?;
+        replace_symlink(fixture_path("server.key"), live_dir.join("key.pem"))?;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        if let Ok(res) = get_version().await {
+            get_res = Some(res);
+            break;
+        }
+    }
+    assert_eq!(get_res.unwrap().status(), http::StatusCode::OK);
+
+    std::fs::remove_dir_all(tls_dir)?;
+    Ok(())
+}
+
+#[it("should serve a client while another client stalls in the TLS handshake")]
+async fn f() -> anyhow::Result<()> {
+    use tokio::io::AsyncReadExt as _;
+
+    let https_port = available_port();
+    let _server_process = run_server_process(
+        &[
+            "--host".to_owned(),
+            "127.0.0.1".to_owned(),
+            "--http-port".to_owned(),
+            available_port().to_string(),
+            "--enable-https".to_owned(),
+            "--https-port".to_owned(),
+            https_port.to_string(),
+            "--crt-path".to_owned(),
+            fixture_path("server.crt").display().to_string(),
+            "--key-path".to_owned(),
+            fixture_path("server.key").display().to_string(),
+            "--tls-handshake-timeout".to_owned(),
+            "1".to_owned(),
+        ],
+        https_port,
+    )
+    .await?;
+
+    // Connect without sending ClientHello
+    let mut stalled_stream = tokio::net::TcpStream::connect(("127.0.0.1", https_port)).await?;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("https://localhost:{https_port}/version"))
+        .body(empty_body())?;
+    let get_res = tokio::time::timeout(
+        std::time::Duration::from_millis(500),
+        https_request(get_req, None),
+    )
+    .await??;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+
+    // The stalled connection is closed after the handshake timeout
+    let n_read = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        stalled_stream.read(&mut [0u8; 1]),
+    )
+    .await??;
+    assert_eq!(n_read, 0);
+    Ok(())
+}
+
+#[it("should serve both HTTP and HTTPS on the unified port")]
+async fn f() -> anyhow::Result<()> {
+    let unified_port = available_port();
+    let _server_process = run_server_process(
+        &[
+            "--host".to_owned(),
+            "127.0.0.1".to_owned(),
+            "--unified-port".to_owned(),
+            unified_port.to_string(),
+            "--crt-path".to_owned(),
+            fixture_path("server.crt").display().to_string(),
+            "--key-path".to_owned(),
+            fixture_path("server.key").display().to_string(),
+        ],
+        unified_port,
+    )
+    .await?;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://127.0.0.1:{unified_port}/help"))
+        .header("Host", format!("localhost:{unified_port}"))
+        .body(empty_body())?;
+    let get_res = http_request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    let help = String::from_utf8(read_all_body(get_res.into_body()).await?)?;
+    assert!(help.contains(&format!("http://localhost:{unified_port}/")));
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("https://localhost:{unified_port}/help"))
+        .header("Host", format!("localhost:{unified_port}"))
+        .body(empty_body())?;
+    let get_res = https_request(get_req, None).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    let help = String::from_utf8(read_all_body(get_res.into_body()).await?)?;
+    assert!(help.contains(&format!("https://localhost:{unified_port}/")));
+    Ok(())
+}
+
+#[it("should redirect HTTP to HTTPS with 308 and add Strict-Transport-Security header to HTTPS")]
+async fn f() -> anyhow::Result<()> {
+    let http_port = available_port();
+    let https_port = available_port();
+    let _server_process = run_server_process(
+        &[
+            "--host".to_owned(),
+            "127.0.0.1".to_owned(),
+            "--http-port".to_owned(),
+            http_port.to_string(),
+            "--enable-https".to_owned(),
+            "--https-port".to_owned(),
+            https_port.to_string(),
+            "--crt-path".to_owned(),
+            fixture_path("server.crt").display().to_string(),
+            "--key-path".to_owned(),
+            fixture_path("server.key").display().to_string(),
+            "--redirect-http-to-https".to_owned(),
+            "--hsts-max-age".to_owned(),
+            "31536000".to_owned(),
+        ],
+        https_port,
+    )
+    .await?;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://127.0.0.1:{http_port}/mypath?n=2"))
+        .body(full_body("this is a content"))?;
+    let send_res = http_request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        get_header_value(send_res.headers(), "location"),
+        Some(format!("https://127.0.0.1:{https_port}/mypath?n=2").as_str())
+    );
+    assert_eq!(
+        get_header_value(send_res.headers(), "strict-transport-security"),
+        None
+    );
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("https://localhost:{https_port}/version"))
+        .body(empty_body())?;
+    let get_res = https_request(get_req, None).await?;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(get_res.headers(), "strict-transport-security"),
+        Some("max-age=31536000")
+    );
+    Ok(())
+}
+
+#[it("should reject the redirect to HTTPS and HSTS without serving HTTPS")]
+async fn f() -> anyhow::Result<()> {
+    for (args, message) in [
+        (
+            vec!["--https-port", "8443", "--redirect-http-to-https"],
+            "--redirect-http-to-https requires --enable-https with --https-port or --unified-port",
+        ),
+        (
+            vec!["--https-port", "8443", "--hsts-max-age", "31536000"],
+            "--hsts-max-age requires --enable-https with --https-port or --unified-port",
+        ),
+    ] {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_piping-server"))
+            .args(["--http-port", &available_port().to_string()])
+            .args(args)
+            .output()?;
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(stderr.contains(message), "{stderr}");
+    }
+    Ok(())
+}